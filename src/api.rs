@@ -4,7 +4,24 @@ pub enum Side {
     Black,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Side {
+    pub const fn opposite(&self) -> Side {
+        match self {
+            Side::White => Side::Black,
+            Side::Black => Side::White,
+        }
+    }
+
+    // Rank the king and rooks start on
+    pub const fn back_rank(&self) -> Rank {
+        match self {
+            Side::White => Rank::R1,
+            Side::Black => Rank::R8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Piece {
     P = 0,
     N = 1,
@@ -14,7 +31,7 @@ pub enum Piece {
     K = 5,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Rank {
     R1 = 0,
     R2 = 1,
@@ -39,6 +56,24 @@ pub enum File {
 }
 
 impl File {
+    pub const fn from_index(i: u8) -> Option<Self> {
+        match i {
+            0 => Some(File::A),
+            1 => Some(File::B),
+            2 => Some(File::C),
+            3 => Some(File::D),
+            4 => Some(File::E),
+            5 => Some(File::F),
+            6 => Some(File::G),
+            7 => Some(File::H),
+            _ => None,
+        }
+    }
+
+    pub const fn to_char(&self) -> char {
+        (b'a' + *self as u8) as char
+    }
+
     pub fn from_char(c: char) -> Option<Self> {
         match c.to_ascii_lowercase() {
             'a' => Some(File::A),
@@ -79,33 +114,140 @@ pub enum SideSet {
     Both,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BoardSide {
     QueenSide,
     KingSide,
 }
 
-#[derive(Debug)]
-enum MoveType {
+impl BoardSide {
+    // Where the king ends up after castling, wherever it started
+    pub const fn king_file(&self) -> File {
+        match self {
+            BoardSide::QueenSide => File::C,
+            BoardSide::KingSide => File::G,
+        }
+    }
+
+    pub const fn rook_file(&self) -> File {
+        match self {
+            BoardSide::QueenSide => File::D,
+            BoardSide::KingSide => File::F,
+        }
+    }
+
+    pub const fn castle_kind(&self) -> MoveType {
+        match self {
+            BoardSide::QueenSide => MoveType::CastleQueen,
+            BoardSide::KingSide => MoveType::CastleKing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveType {
     Quiet,
+    DoublePush,
     Capture,
+    EnPassant,
     CastleQueen,
     CastleKing,
     Promote(PromotionType),
     PromoteCapture(PromotionType),
 }
 
-#[derive(Debug)]
-enum PromotionType {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromotionType {
     Queen,
     Rook,
     Bishop,
     Knight,
 }
 
+impl PromotionType {
+    pub const fn piece(&self) -> Piece {
+        match self {
+            PromotionType::Queen => Piece::Q,
+            PromotionType::Rook => Piece::R,
+            PromotionType::Bishop => Piece::B,
+            PromotionType::Knight => Piece::N,
+        }
+    }
+}
+
+// Lower case, as in `e7e8q`
+impl std::fmt::Display for PromotionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let c = match self {
+            PromotionType::Queen => 'q',
+            PromotionType::Rook => 'r',
+            PromotionType::Bishop => 'b',
+            PromotionType::Knight => 'n',
+        };
+        write!(f, "{}", c)
+    }
+}
+
+impl std::str::FromStr for PromotionType {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "q" | "Q" => Ok(PromotionType::Queen),
+            "r" | "R" => Ok(PromotionType::Rook),
+            "b" | "B" => Ok(PromotionType::Bishop),
+            "n" | "N" => Ok(PromotionType::Knight),
+            _ => Err(NotationError::syntax(s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotationErrorKind {
+    // Not a square, promotion piece or coordinate move
+    Syntax,
+    // Well formed, but not a legal move in the position
+    Illegal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotationError {
+    pub text: String,
+    pub kind: NotationErrorKind,
+}
+
+impl NotationError {
+    pub fn syntax(text: &str) -> Self {
+        NotationError {
+            text: text.to_string(),
+            kind: NotationErrorKind::Syntax,
+        }
+    }
+
+    pub fn illegal(text: &str) -> Self {
+        NotationError {
+            text: text.to_string(),
+            kind: NotationErrorKind::Illegal,
+        }
+    }
+}
+
+impl std::fmt::Display for NotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            NotationErrorKind::Syntax => write!(f, "'{}' is not coordinate notation", self.text),
+            NotationErrorKind::Illegal => write!(f, "'{}' is not a legal move", self.text),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
 // Direction and a distance
-type Compass = (Square, Direction, u8);
+pub type Compass = (Square, Direction, u8);
 
-enum Direction {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
     N,
     NE,
     E,
@@ -117,6 +259,48 @@ enum Direction {
     Equal,
 }
 
+impl Direction {
+    pub const COMPASS: [Direction; 8] = [
+        Direction::N,
+        Direction::NE,
+        Direction::E,
+        Direction::SE,
+        Direction::S,
+        Direction::SW,
+        Direction::W,
+        Direction::NW,
+    ];
+
+    // (rank, file) step of a single move in this direction
+    pub const fn step(&self) -> (i8, i8) {
+        match self {
+            Direction::N => (1, 0),
+            Direction::NE => (1, 1),
+            Direction::E => (0, 1),
+            Direction::SE => (-1, 1),
+            Direction::S => (-1, 0),
+            Direction::SW => (-1, -1),
+            Direction::W => (0, -1),
+            Direction::NW => (1, -1),
+            Direction::Equal => (0, 0),
+        }
+    }
+
+    pub const fn opposite(&self) -> Direction {
+        match self {
+            Direction::N => Direction::S,
+            Direction::NE => Direction::SW,
+            Direction::E => Direction::W,
+            Direction::SE => Direction::NW,
+            Direction::S => Direction::N,
+            Direction::SW => Direction::NE,
+            Direction::W => Direction::E,
+            Direction::NW => Direction::SE,
+            Direction::Equal => Direction::Equal,
+        }
+    }
+}
+
 pub trait GameState {
     fn start() -> Self;
 
@@ -127,7 +311,12 @@ pub trait GameState {
 
     fn is_legal(&self) -> bool;
 
-    fn make_move(&self, m: Move) -> Self;
+    // State needed to take a move back
+    type Undo;
+
+    fn make_move(&mut self, m: Move) -> Self::Undo;
+
+    fn unmake_move(&mut self, m: Move, undo: Self::Undo);
 }
 
 pub trait PieceState {
@@ -137,28 +326,127 @@ pub trait PieceState {
     // Print the pieces
     fn pretty_print(&self) -> String;
 
-    fn make_move(&self, m: Move) -> Self;
+    // Move the pieces in place, returning the captured piece
+    fn make_move(&mut self, m: Move) -> Option<Piece>;
+
+    // Put the pieces back given the piece `make_move` captured
+    fn unmake_move(&mut self, m: Move, captured: Option<Piece>);
 
     fn start() -> Self;
 
     fn empty() -> Self;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Move {
     from: Square,
     to: Square,
+    kind: MoveType,
+}
+
+impl Move {
+    pub const fn new(from: Square, to: Square, kind: MoveType) -> Self {
+        Move { from, to, kind }
+    }
+
+    pub const fn from(&self) -> Square {
+        self.from
+    }
+
+    pub const fn to(&self) -> Square {
+        self.to
+    }
+
+    pub const fn kind(&self) -> MoveType {
+        self.kind
+    }
+
+    pub const fn castling_side(&self) -> Option<BoardSide> {
+        match self.kind {
+            MoveType::CastleQueen => Some(BoardSide::QueenSide),
+            MoveType::CastleKing => Some(BoardSide::KingSide),
+            _ => None,
+        }
+    }
+
+    // Coordinate notation as used by UCI. Castling is written as the king's own
+    // move, `e1g1`, or as the king taking its rook, `e1h1`, which Chess960 GUIs
+    // expect.
+    pub fn to_uci(&self, king_takes_rook: bool) -> String {
+        let to = match self.castling_king_to() {
+            Some(king_to) if !king_takes_rook => king_to,
+            _ => self.to,
+        };
+        match self.kind {
+            MoveType::Promote(p) | MoveType::PromoteCapture(p) => {
+                format!("{}{}{}", self.from, to, p)
+            }
+            _ => format!("{}{}", self.from, to),
+        }
+    }
+
+    // Castling is stored as the king taking its own rook, so `to` is the rook's
+    // square. This works for Chess960, where the king may not move at all.
+    pub const fn castling_king_to(&self) -> Option<Square> {
+        match self.castling_side() {
+            Some(wing) => Some(Square::new(self.from.v / 8 * 8 + wing.king_file() as u8)),
+            None => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_uci(false))
+    }
+}
+
+// Without a position the kind can't be known, so this gives `Quiet` or `Promote`.
+// `BbBoardState::parse_uci` finds the actual legal move.
+impl std::str::FromStr for Move {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || NotationError::syntax(s);
+        let from = s
+            .get(0..2)
+            .ok_or_else(error)?
+            .parse()
+            .map_err(|_| error())?;
+        let to = s
+            .get(2..4)
+            .ok_or_else(error)?
+            .parse()
+            .map_err(|_| error())?;
+        let kind = match s.get(4..).ok_or_else(error)? {
+            "" => MoveType::Quiet,
+            p => MoveType::Promote(p.parse().map_err(|_| error())?),
+        };
+        Ok(Move::new(from, to, kind))
+    }
 }
 
 pub trait Board {
-    fn make_move(&self, m: Move) -> Self;
+    // State needed to take a move back
+    type Undo;
+
+    fn make_move(&mut self, m: Move) -> Self::Undo;
+
+    // Restore the position from before `make_move(m)` returned `undo`
+    fn unmake_move(&mut self, m: Move, undo: Self::Undo);
 
     fn is_legal(&self) -> bool;
 
     fn test_move(&self, m: Move) -> bool;
 
+    // Every strictly legal move for the side to move
+    fn legal_moves(&self) -> Vec<Move>;
+
     fn pretty_print(&self) -> String;
 
-    fn from_fen(s: String) -> Self;
+    fn from_fen(s: String) -> Option<Self>
+    where
+        Self: Sized;
 
     fn empty() -> Self;
 
@@ -167,7 +455,7 @@ pub trait Board {
     // TODO: validate function
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Square {
     pub v: u8,
 }
@@ -182,4 +470,34 @@ impl Square {
             v: (r as u8) * 8 + (f as u8),
         }
     }
+
+    pub const fn rank_index(&self) -> u8 {
+        self.v / 8
+    }
+
+    pub const fn file_index(&self) -> u8 {
+        self.v % 8
+    }
+}
+
+impl std::fmt::Display for Square {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            (b'a' + self.file_index()) as char,
+            (b'1' + self.rank_index()) as char
+        )
+    }
+}
+
+impl std::str::FromStr for Square {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[f @ b'a'..=b'h', r @ b'1'..=b'8'] => Ok(Square::new((r - b'1') * 8 + f - b'a')),
+            _ => Err(NotationError::syntax(s)),
+        }
+    }
 }
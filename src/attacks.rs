@@ -0,0 +1,265 @@
+use crate::api::{Piece, Side, SideSet, Square};
+use crate::bitboard::{BbBoardState, BbPieceState, Bitboard};
+use crate::magic;
+use crate::tables::{BETWEEN, KING_ATTACKS, KNIGHT_ATTACKS, LINE, PAWN_ATTACKS};
+
+const ROOK_STEPS: [(i8, i8); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+const BISHOP_STEPS: [(i8, i8); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+// Square reached by a (rank, file) step, if it stays on the board
+fn step(s: Square, (dr, df): (i8, i8)) -> Option<Square> {
+    let r = s.rank_index() as i8 + dr;
+    let f = s.file_index() as i8 + df;
+    if (0..8).contains(&r) && (0..8).contains(&f) {
+        Some(Square::new((r * 8 + f) as u8))
+    } else {
+        None
+    }
+}
+
+// Walk each ray until it leaves the board or hits an occupied square, which is included
+fn slider_attacks(s: Square, occupied: Bitboard, steps: &[(i8, i8)]) -> Bitboard {
+    let mut bb = Bitboard::empty();
+    for &d in steps {
+        let mut cur = s;
+        while let Some(t) = step(cur, d) {
+            let coord = Bitboard::get_coord(t);
+            bb |= coord;
+            if !occupied.is_disjoint(coord) {
+                break;
+            }
+            cur = t;
+        }
+    }
+    bb
+}
+
+#[inline]
+pub fn knight_attacks(s: Square) -> Bitboard {
+    KNIGHT_ATTACKS[s.v as usize]
+}
+
+#[inline]
+pub fn king_attacks(s: Square) -> Bitboard {
+    KING_ATTACKS[s.v as usize]
+}
+
+// Squares attacked by a pawn of `side` standing on `s`
+#[inline]
+pub fn pawn_attacks(s: Square, side: Side) -> Bitboard {
+    PAWN_ATTACKS[side as usize][s.v as usize]
+}
+
+pub fn rook_attacks(s: Square, occupied: Bitboard) -> Bitboard {
+    magic::rook_attacks(s, occupied)
+}
+
+pub fn bishop_attacks(s: Square, occupied: Bitboard) -> Bitboard {
+    magic::bishop_attacks(s, occupied)
+}
+
+// Ray walking versions of the slider attacks, used to build the magic tables
+pub(crate) fn rook_rays(s: Square, occupied: Bitboard) -> Bitboard {
+    slider_attacks(s, occupied, &ROOK_STEPS)
+}
+
+pub(crate) fn bishop_rays(s: Square, occupied: Bitboard) -> Bitboard {
+    slider_attacks(s, occupied, &BISHOP_STEPS)
+}
+
+pub fn queen_attacks(s: Square, occupied: Bitboard) -> Bitboard {
+    rook_attacks(s, occupied) | bishop_attacks(s, occupied)
+}
+
+// Squares strictly between `a` and `b`. Empty if they are not aligned.
+#[inline]
+pub fn between(a: Square, b: Square) -> Bitboard {
+    BETWEEN[a.v as usize][b.v as usize]
+}
+
+// The whole rank, file or diagonal through `a` and `b`. Empty if they are not aligned.
+#[inline]
+pub fn line(a: Square, b: Square) -> Bitboard {
+    LINE[a.v as usize][b.v as usize]
+}
+
+// All pieces of side `by` that attack `s`, given the occupancy `occupied`
+pub fn attackers_of(ps: &BbPieceState, s: Square, by: Side, occupied: Bitboard) -> Bitboard {
+    use crate::api::Piece::*;
+
+    let queens = ps.get(by, Q);
+    (pawn_attacks(s, by.opposite()) & ps.get(by, P))
+        | (knight_attacks(s) & ps.get(by, N))
+        | (king_attacks(s) & ps.get(by, K))
+        | (rook_attacks(s, occupied) & (ps.get(by, R) | queens))
+        | (bishop_attacks(s, occupied) & (ps.get(by, B) | queens))
+}
+
+// Is `s` attacked by any piece of side `by`, given the occupancy `occupied`
+pub fn is_attacked_by(ps: &BbPieceState, s: Square, by: Side, occupied: Bitboard) -> bool {
+    use crate::api::Piece::*;
+
+    let queens = ps.get(by, Q);
+    !(pawn_attacks(s, by.opposite()).is_disjoint(ps.get(by, P))
+        && knight_attacks(s).is_disjoint(ps.get(by, N))
+        && king_attacks(s).is_disjoint(ps.get(by, K))
+        && rook_attacks(s, occupied).is_disjoint(ps.get(by, R) | queens)
+        && bishop_attacks(s, occupied).is_disjoint(ps.get(by, B) | queens))
+}
+
+impl BbBoardState {
+    // Pieces of the sides in `by` attacking `s`
+    pub fn attackers_to(&self, s: Square, by: SideSet) -> Bitboard {
+        let occupied = self.pieces.occupied();
+        let of = |side| attackers_of(&self.pieces, s, side, occupied);
+        match by {
+            SideSet::None => Bitboard::empty(),
+            SideSet::White => of(Side::White),
+            SideSet::Black => of(Side::Black),
+            SideSet::Both => of(Side::White) | of(Side::Black),
+        }
+    }
+
+    pub fn is_attacked(&self, s: Square, by: Side) -> bool {
+        is_attacked_by(&self.pieces, s, by, self.pieces.occupied())
+    }
+
+    // Enemy pieces giving check to the side to move
+    pub fn checkers(&self) -> Bitboard {
+        let kings = self.pieces.get(self.to_move, Piece::K);
+        match kings.is_empty() {
+            true => Bitboard::empty(),
+            false => attackers_of(
+                &self.pieces,
+                kings.lsb(),
+                self.to_move.opposite(),
+                self.pieces.occupied(),
+            ),
+        }
+    }
+
+    // Pieces of `side` that are the only blocker between their king and an enemy
+    // slider, and the sliders pinning them
+    pub fn pinned(&self, side: Side) -> (Bitboard, Bitboard) {
+        let kings = self.pieces.get(side, Piece::K);
+        if kings.is_empty() {
+            return (Bitboard::empty(), Bitboard::empty());
+        }
+        let king = kings.lsb();
+        let them = side.opposite();
+        let own = self.pieces.side(side);
+        let occupied = self.pieces.occupied();
+        let enemy = self.pieces.side(them);
+        let queens = self.pieces.get(them, Piece::Q);
+        let snipers = (rook_attacks(king, enemy) & (self.pieces.get(them, Piece::R) | queens))
+            | (bishop_attacks(king, enemy) & (self.pieces.get(them, Piece::B) | queens));
+
+        let mut pinned = Bitboard::empty();
+        let mut pinners = Bitboard::empty();
+        for sniper in snipers {
+            let blockers = between(king, sniper) & occupied;
+            if blockers.count_bits() == 1 && blockers.is_subset(own) {
+                pinned |= blockers;
+                pinners |= Bitboard::get_coord(sniper);
+            }
+        }
+        (pinned, pinners)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::attacks::*;
+    use crate::bitboard::parse_fen;
+
+    #[test]
+    fn knight_in_corner() {
+        assert!(knight_attacks(Square::new(0)).v == 0x20400);
+    }
+
+    #[test]
+    fn knight_in_center() {
+        // d4
+        assert!(knight_attacks(Square::new(27)).count_bits() == 8);
+    }
+
+    #[test]
+    fn king_on_edge() {
+        // a4
+        assert!(king_attacks(Square::new(24)).count_bits() == 5);
+    }
+
+    #[test]
+    fn pawn_attacks_do_not_wrap() {
+        // h2 and a7
+        assert!(pawn_attacks(Square::new(15), Side::White).v == 1 << 22);
+        assert!(pawn_attacks(Square::new(48), Side::Black).v == 1 << 41);
+    }
+
+    #[test]
+    fn rook_stops_at_blocker() {
+        // a1 with a blocker on a3 and c1
+        let occ = Bitboard::new((1 << 16) | (1 << 2));
+        assert!(rook_attacks(Square::new(0), occ).v == (1 << 8) | (1 << 16) | (1 << 1) | (1 << 2));
+    }
+
+    #[test]
+    fn between_aligned_squares() {
+        // a1 and d4
+        assert!(between(Square::new(0), Square::new(27)).v == (1 << 9) | (1 << 18));
+        // a1 and h1
+        assert!(between(Square::new(0), Square::new(7)).count_bits() == 6);
+        // a1 and b3
+        assert!(between(Square::new(0), Square::new(17)).is_empty());
+    }
+
+    #[test]
+    fn line_through_squares() {
+        // b2 and c3 lie on the long diagonal
+        assert!(line(Square::new(9), Square::new(18)).v == 0x8040201008040201);
+        // a1 and b3
+        assert!(line(Square::new(0), Square::new(17)).is_empty());
+    }
+
+    #[test]
+    fn bishop_empty_board() {
+        // d4
+        assert!(bishop_attacks(Square::new(27), Bitboard::empty()).count_bits() == 13);
+    }
+
+    #[test]
+    fn attackers_of_a_square() {
+        let fen = "4k3/8/8/3p4/4R3/5N2/8/4K3 w - - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        // e4 only by the d5 pawn
+        let e4 = Square::new(28);
+        assert!(board.attackers_to(e4, SideSet::Black).v == 1 << 35);
+        assert!(board.attackers_to(e4, SideSet::White).is_empty());
+        // d4 by the rook and knight but not by the pawn in front of it
+        let d4 = Square::new(27);
+        assert!(board.attackers_to(d4, SideSet::Both).v == (1 << 28) | (1 << 21));
+        assert!(board.attackers_to(d4, SideSet::None).is_empty());
+        assert!(board.is_attacked(d4, Side::White) && !board.is_attacked(d4, Side::Black));
+    }
+
+    #[test]
+    fn checkers_and_pins() {
+        // Double check from the e8 rook and the h4 bishop
+        let fen = "4r1k1/8/8/8/7b/8/8/4K3 w - - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.checkers().v == (1 << 60) | (1 << 31));
+
+        // The d2 knight is pinned by the a5 queen, the e2 bishop by the e8 rook
+        // and black's g7 pawn by the g1 queen
+        let fen = "4r1k1/6p1/8/q7/8/8/3NB3/4K1Q1 w - - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.checkers().is_empty());
+        let (pinned, pinners) = board.pinned(Side::White);
+        assert!(pinned.v == (1 << 11) | (1 << 12));
+        assert!(pinners.v == (1 << 32) | (1 << 60));
+        let (pinned, pinners) = board.pinned(Side::Black);
+        assert!(pinned.v == 1 << 54 && pinners.v == 1 << 6);
+    }
+}
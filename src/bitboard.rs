@@ -2,8 +2,13 @@ use std::fmt::Binary;
 use std::ops::*;
 use std::str::Chars;
 
-use crate::api::{File, PieceState, Rank, Side, Square};
+use crate::api::{
+    Board, BoardSide, Compass, Direction, File, Move, MoveType, Piece, PieceState, Rank, Side,
+    Square,
+};
+use crate::castling::CastlingRights;
 use crate::utils::count_bits;
+use crate::zobrist;
 
 pub const TOP_LEFT: [Square; 64] = [
     Square::new(56),
@@ -71,33 +76,97 @@ pub const TOP_LEFT: [Square; 64] = [
     Square::new(6),
     Square::new(7),
 ];
-#[derive(PartialEq)]
-struct BbPieceState {
-    wp: Bitboard,
-    wr: Bitboard,
-    wn: Bitboard,
-    wb: Bitboard,
-    wq: Bitboard,
-    wk: Bitboard,
-    bp: Bitboard,
-    br: Bitboard,
-    bn: Bitboard,
-    bb: Bitboard,
-    bq: Bitboard,
-    bk: Bitboard,
+#[derive(Debug, Clone, PartialEq)]
+pub struct BbPieceState {
+    pub(crate) wp: Bitboard,
+    pub(crate) wr: Bitboard,
+    pub(crate) wn: Bitboard,
+    pub(crate) wb: Bitboard,
+    pub(crate) wq: Bitboard,
+    pub(crate) wk: Bitboard,
+    pub(crate) bp: Bitboard,
+    pub(crate) br: Bitboard,
+    pub(crate) bn: Bitboard,
+    pub(crate) bb: Bitboard,
+    pub(crate) bq: Bitboard,
+    pub(crate) bk: Bitboard,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct BbBoardState {
-    pieces: BbPieceState,
-    to_move: Side,
-    en_passant: Option<File>,
-    reversable_moves: u8,
-    w_kingside_castling: bool,
-    w_queenside_castling: bool,
-    b_kingside_castling: bool,
-    b_queenside_castling: bool,
+    pub(crate) pieces: BbPieceState,
+    pub(crate) to_move: Side,
+    // Square a pawn that just made a double push passed over
+    pub(crate) en_passant: Option<Square>,
+    // Halfmoves since the last capture or pawn move
+    pub(crate) reversable_moves: u16,
+    pub(crate) full_moves: u16,
+    pub(crate) castling: CastlingRights,
+    pub(crate) hash: u64,
 }
 
+impl BbPieceState {
+    pub fn get(&self, side: Side, piece: Piece) -> Bitboard {
+        match (side, piece) {
+            (Side::White, Piece::P) => self.wp,
+            (Side::White, Piece::N) => self.wn,
+            (Side::White, Piece::B) => self.wb,
+            (Side::White, Piece::R) => self.wr,
+            (Side::White, Piece::Q) => self.wq,
+            (Side::White, Piece::K) => self.wk,
+            (Side::Black, Piece::P) => self.bp,
+            (Side::Black, Piece::N) => self.bn,
+            (Side::Black, Piece::B) => self.bb,
+            (Side::Black, Piece::R) => self.br,
+            (Side::Black, Piece::Q) => self.bq,
+            (Side::Black, Piece::K) => self.bk,
+        }
+    }
+
+    pub fn get_mut(&mut self, side: Side, piece: Piece) -> &mut Bitboard {
+        match (side, piece) {
+            (Side::White, Piece::P) => &mut self.wp,
+            (Side::White, Piece::N) => &mut self.wn,
+            (Side::White, Piece::B) => &mut self.wb,
+            (Side::White, Piece::R) => &mut self.wr,
+            (Side::White, Piece::Q) => &mut self.wq,
+            (Side::White, Piece::K) => &mut self.wk,
+            (Side::Black, Piece::P) => &mut self.bp,
+            (Side::Black, Piece::N) => &mut self.bn,
+            (Side::Black, Piece::B) => &mut self.bb,
+            (Side::Black, Piece::R) => &mut self.br,
+            (Side::Black, Piece::Q) => &mut self.bq,
+            (Side::Black, Piece::K) => &mut self.bk,
+        }
+    }
+
+    // All squares occupied by one side
+    pub fn side(&self, side: Side) -> Bitboard {
+        match side {
+            Side::White => self.wp | self.wn | self.wb | self.wr | self.wq | self.wk,
+            Side::Black => self.bp | self.bn | self.bb | self.br | self.bq | self.bk,
+        }
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.side(Side::White) | self.side(Side::Black)
+    }
+
+    pub fn piece_at(&self, s: Square) -> Option<(Side, Piece)> {
+        let coord = Bitboard::get_coord(s);
+        for side in [Side::White, Side::Black] {
+            for piece in PIECES {
+                if !self.get(side, piece).is_disjoint(coord) {
+                    return Some((side, piece));
+                }
+            }
+        }
+        None
+    }
+}
+
+pub const PIECES: [Piece; 6] = [Piece::P, Piece::N, Piece::B, Piece::R, Piece::Q, Piece::K];
+
 impl PieceState for BbPieceState {
     fn pretty_print(&self) -> String {
         let mut s = String::new();
@@ -172,176 +241,663 @@ impl PieceState for BbPieceState {
     }
 
     fn is_legal(&self) -> bool {
-        fn correct_number_of_kings(ps: &BbPieceState) -> bool {
-            let kings = ps.bk | ps.wk;
-            kings.count_bits() == 2
+        self.validate().is_empty()
+    }
+
+    fn make_move(&mut self, m: Move) -> Option<Piece> {
+        let from = Bitboard::get_coord(m.from());
+        let to = Bitboard::get_coord(m.to());
+        let (side, piece) = self.piece_at(m.from())?;
+        let them = side.opposite();
+
+        let captured = match m.kind() {
+            MoveType::Capture | MoveType::PromoteCapture(_) => {
+                let (_, captured) = self.piece_at(m.to())?;
+                *self.get_mut(them, captured) ^= to;
+                Some(captured)
+            }
+            MoveType::EnPassant => {
+                *self.get_mut(them, Piece::P) ^= en_passant_victim(m);
+                Some(Piece::P)
+            }
+            MoveType::CastleKing | MoveType::CastleQueen => {
+                self.toggle_castling(side, m);
+                return None;
+            }
+            _ => None,
+        };
+
+        match m.kind() {
+            MoveType::Promote(p) | MoveType::PromoteCapture(p) => {
+                *self.get_mut(side, piece) ^= from;
+                *self.get_mut(side, p.piece()) ^= to;
+            }
+            _ => *self.get_mut(side, piece) ^= from | to,
+        }
+        captured
+    }
+
+    fn unmake_move(&mut self, m: Move, captured: Option<Piece>) {
+        if let Some(king_to) = m.castling_king_to() {
+            if let Some((side, Piece::K)) = self.piece_at(king_to) {
+                self.toggle_castling(side, m);
+            }
+            return;
+        }
+        let from = Bitboard::get_coord(m.from());
+        let to = Bitboard::get_coord(m.to());
+        let (side, piece) = match self.piece_at(m.to()) {
+            Some(x) => x,
+            None => return,
+        };
+        let them = side.opposite();
+
+        match m.kind() {
+            MoveType::Promote(p) | MoveType::PromoteCapture(p) => {
+                *self.get_mut(side, p.piece()) ^= to;
+                *self.get_mut(side, Piece::P) ^= from;
+            }
+            _ => *self.get_mut(side, piece) ^= from | to,
+        }
+
+        match (m.kind(), captured) {
+            (MoveType::EnPassant, _) => *self.get_mut(them, Piece::P) ^= en_passant_victim(m),
+            (_, Some(captured)) => *self.get_mut(them, captured) ^= to,
+            _ => (),
+        }
+    }
+}
+
+// Square of the pawn taken by an en passant capture, one rank behind the target
+fn en_passant_victim(m: Move) -> Bitboard {
+    Bitboard::get_coord(Square::new(m.from().rank_index() * 8 + m.to().file_index()))
+}
+
+// King and rook destinations of a castling move
+fn castling_targets(m: Move, wing: BoardSide) -> (Square, Square) {
+    let rank = m.from().rank_index() * 8;
+    (
+        Square::new(rank + wing.king_file() as u8),
+        Square::new(rank + wing.rook_file() as u8),
+    )
+}
+
+impl BbPieceState {
+    // Castling moves the king from `m.from()` and the rook from `m.to()`. In Chess960
+    // either may land on the other's square, or stay put, so each piece is toggled
+    // separately. Doing it twice undoes it.
+    fn toggle_castling(&mut self, side: Side, m: Move) {
+        let wing = match m.castling_side() {
+            Some(wing) => wing,
+            None => return,
+        };
+        let (king_to, rook_to) = castling_targets(m, wing);
+        *self.get_mut(side, Piece::K) ^=
+            Bitboard::get_coord(m.from()) ^ Bitboard::get_coord(king_to);
+        *self.get_mut(side, Piece::R) ^= Bitboard::get_coord(m.to()) ^ Bitboard::get_coord(rook_to);
+    }
+}
+
+// Everything `make_move` changes on a `BbBoardState` that cannot be recomputed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Undo {
+    captured: Option<Piece>,
+    en_passant: Option<Square>,
+    reversable_moves: u16,
+    full_moves: u16,
+    castling: CastlingRights,
+    hash: u64,
+}
+
+impl BbBoardState {
+    pub fn halfmove_clock(&self) -> u16 {
+        self.reversable_moves
+    }
+
+    pub fn fullmove_number(&self) -> u16 {
+        self.full_moves
+    }
+
+    fn is_checkmate(&self) -> bool {
+        self.in_check() && self.legal_moves().is_empty()
+    }
+
+    // A draw can be claimed after 50 moves by each side without a capture or pawn
+    // move, unless the last of them gave mate
+    pub fn is_fifty_move_draw(&self) -> bool {
+        self.reversable_moves >= 100 && !self.is_checkmate()
+    }
+
+    // After 75 moves the game is drawn without a claim
+    pub fn is_seventy_five_move_draw(&self) -> bool {
+        self.reversable_moves >= 150 && !self.is_checkmate()
+    }
+}
+
+impl Board for BbBoardState {
+    type Undo = Undo;
+
+    fn make_move(&mut self, m: Move) -> Undo {
+        let keys = zobrist::keys();
+        let mover = self.pieces.piece_at(m.from());
+        let mut undo = Undo {
+            captured: None,
+            en_passant: self.en_passant,
+            reversable_moves: self.reversable_moves,
+            full_moves: self.full_moves,
+            castling: self.castling,
+            hash: self.hash,
+        };
+        let mut hash = self.hash ^ keys.side ^ keys.castling(self) ^ self.en_passant_hash();
+
+        undo.captured = self.pieces.make_move(m);
+        match (mover, m.castling_side()) {
+            (Some((side, _)), Some(wing)) => {
+                let (king_to, rook_to) = castling_targets(m, wing);
+                hash ^= keys.piece(side, Piece::K, m.from())
+                    ^ keys.piece(side, Piece::K, king_to)
+                    ^ keys.piece(side, Piece::R, m.to())
+                    ^ keys.piece(side, Piece::R, rook_to);
+            }
+            (Some((side, piece)), None) => {
+                let placed = match m.kind() {
+                    MoveType::Promote(p) | MoveType::PromoteCapture(p) => p.piece(),
+                    _ => piece,
+                };
+                hash ^= keys.piece(side, piece, m.from()) ^ keys.piece(side, placed, m.to());
+                match (m.kind(), undo.captured) {
+                    (MoveType::EnPassant, _) => {
+                        hash ^= keys.piece(side.opposite(), Piece::P, en_passant_victim(m).lsb())
+                    }
+                    (_, Some(captured)) => hash ^= keys.piece(side.opposite(), captured, m.to()),
+                    _ => (),
+                }
+            }
+            (None, _) => (),
+        }
+
+        // Rights go when the king moves or a castling rook moves or is taken
+        if let Some((side, Piece::K)) = mover {
+            self.castling.clear_side(side);
+        }
+        self.castling.clear_rook(m.from());
+        self.castling.clear_rook(m.to());
+        self.en_passant = match m.kind() {
+            MoveType::DoublePush => Some(Square::new((m.from().v + m.to().v) / 2)),
+            _ => None,
+        };
+        self.reversable_moves = if matches!(mover, Some((_, Piece::P))) || undo.captured.is_some() {
+            0
+        } else {
+            self.reversable_moves.saturating_add(1)
+        };
+        if self.to_move == Side::Black {
+            self.full_moves = self.full_moves.saturating_add(1);
         }
+        self.to_move = self.to_move.opposite();
+        self.hash = hash ^ keys.castling(self) ^ self.en_passant_hash();
+        undo
+    }
+
+    fn unmake_move(&mut self, m: Move, undo: Undo) {
+        self.to_move = self.to_move.opposite();
+        self.pieces.unmake_move(m, undo.captured);
+        self.en_passant = undo.en_passant;
+        self.reversable_moves = undo.reversable_moves;
+        self.full_moves = undo.full_moves;
+        self.castling = undo.castling;
+        self.hash = undo.hash;
+    }
+
+    fn is_legal(&self) -> bool {
+        self.validate().is_empty()
+    }
+
+    fn test_move(&self, m: Move) -> bool {
+        self.legal_moves().contains(&m)
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        BbBoardState::legal_moves(self)
+    }
 
-        fn no_pieces_on_same_square(ps: &BbPieceState) -> bool {
-            let ps = ps.wp
-                & ps.wr
-                & ps.wn
-                & ps.wb
-                & ps.wq
-                & ps.wk
-                & ps.bp
-                & ps.br
-                & ps.bn
-                & ps.bb
-                & ps.bq
-                & ps.bk;
-            ps.v == 0
+    fn pretty_print(&self) -> String {
+        self.pieces.pretty_print()
+    }
+
+    fn from_fen(s: String) -> Option<Self> {
+        parse_fen(s).ok()
+    }
+
+    fn empty() -> Self {
+        BbBoardState {
+            pieces: BbPieceState::empty(),
+            to_move: Side::White,
+            en_passant: None,
+            reversable_moves: 0,
+            full_moves: 1,
+            castling: CastlingRights::NONE,
+            hash: 0,
         }
+    }
+}
 
-        return correct_number_of_kings(self) && no_pieces_on_same_square(self);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FenErrorKind {
+    UnexpectedEnd,
+    // Expected one character, found another
+    Expected(char, char),
+    BadPiece(char),
+    TooManySquares(Rank),
+    TooFewSquares(Rank),
+    BadSideToMove(char),
+    BadCastling(char),
+    BadEnPassant(char),
+    BadCounter(char),
+    CounterOutOfRange(u32),
+    TrailingCharacters,
+}
+
+// What went wrong and the character offset into the FEN where it was noticed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FenError {
+    pub offset: usize,
+    pub kind: FenErrorKind,
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            FenErrorKind::UnexpectedEnd => write!(f, "FEN ends early"),
+            FenErrorKind::Expected(e, c) => write!(f, "expected '{}', found '{}'", e, c),
+            FenErrorKind::BadPiece(c) => write!(f, "'{}' is not a piece", c),
+            FenErrorKind::TooManySquares(r) => {
+                write!(f, "rank {} has too many squares", r as u8 + 1)
+            }
+            FenErrorKind::TooFewSquares(r) => write!(f, "rank {} has too few squares", r as u8 + 1),
+            FenErrorKind::BadSideToMove(c) => {
+                write!(f, "side to move must be 'w' or 'b', found '{}'", c)
+            }
+            FenErrorKind::BadCastling(c) => write!(f, "'{}' is not a castling right", c),
+            FenErrorKind::BadEnPassant(c) => write!(f, "bad en passant square at '{}'", c),
+            FenErrorKind::BadCounter(c) => write!(f, "move counter has '{}'", c),
+            FenErrorKind::CounterOutOfRange(x) => write!(f, "move counter {} is out of range", x),
+            FenErrorKind::TrailingCharacters => write!(f, "unexpected characters after the FEN"),
+        }?;
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FenOptions {
+    // Accept FENs that stop after the en passant field, as EPD and pasted positions
+    // often do. The counters then default to 0 and 1.
+    pub lenient: bool,
+    // Drop the en passant square unless a pawn can legally capture on it, as
+    // Polyglot hashing expects
+    pub legal_en_passant_only: bool,
+}
+
+// Reads characters while keeping track of the offset for errors
+struct FenCursor<'a> {
+    chars: Chars<'a>,
+    offset: usize,
+}
+
+impl FenCursor<'_> {
+    fn next(&mut self) -> Result<char, FenError> {
+        let c = self.chars.next().ok_or(FenError {
+            offset: self.offset,
+            kind: FenErrorKind::UnexpectedEnd,
+        })?;
+        self.offset += 1;
+        Ok(c)
     }
 
-    fn make_move(&self, m: crate::api::Move) -> Self {
-        todo!()
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
     }
+
+    fn at_end(&self) -> bool {
+        self.peek().is_none()
+    }
+
+    // Error for the character returned by the last `next`
+    fn error(&self, kind: FenErrorKind) -> FenError {
+        FenError {
+            offset: self.offset.saturating_sub(1),
+            kind,
+        }
+    }
+}
+
+pub fn parse_fen(s: String) -> Result<BbBoardState, FenError> {
+    parse_fen_with(s, FenOptions::default())
+}
+
+pub fn parse_fen_lenient(s: String) -> Result<BbBoardState, FenError> {
+    parse_fen_with(
+        s,
+        FenOptions {
+            lenient: true,
+            ..Default::default()
+        },
+    )
 }
 
-pub fn parse_fen(s: String) -> Option<BbBoardState> {
-    let mut chars = s.as_str().chars();
+pub fn parse_fen_with(s: String, options: FenOptions) -> Result<BbBoardState, FenError> {
+    let s = if options.lenient {
+        s.trim()
+    } else {
+        s.as_str()
+    };
+    let mut chars = FenCursor {
+        chars: s.chars(),
+        offset: 0,
+    };
     let mut pieces = BbPieceState::empty();
     parse_line(&mut chars, &mut pieces, Rank::R8)?;
-    parse_char(&mut chars, &'/');
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R7)?;
-    parse_char(&mut chars, &'/')?;
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R6)?;
-    parse_char(&mut chars, &'/')?;
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R5)?;
-    parse_char(&mut chars, &'/')?;
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R4)?;
-    parse_char(&mut chars, &'/')?;
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R3)?;
-    parse_char(&mut chars, &'/')?;
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R2)?;
-    parse_char(&mut chars, &'/')?;
+    parse_char(&mut chars, '/')?;
     parse_line(&mut chars, &mut pieces, Rank::R1)?;
-    parse_char(&mut chars, &' ')?;
+    parse_char(&mut chars, ' ')?;
     let to_move = parse_to_move(&mut chars)?;
-    parse_char(&mut chars, &' ')?;
-    let (w_kingside_castling, w_queenside_castling, b_kingside_castling, b_queenside_castling) =
-        parse_castling(&mut chars)?;
-    let en_passant = parse_en_passant(&mut chars)?;
-    parse_char(&mut chars, &' ')?;
-    let reversable_moves = parse_num(&mut chars)? as u8;
-    //let _whole_moves = parse_num(&mut chars)?;
-
-    fn parse_line(chars: &mut Chars, ps: &mut BbPieceState, r: Rank) -> Option<()> {
+    parse_char(&mut chars, ' ')?;
+    let castling = parse_castling(&mut chars, &pieces)?;
+    parse_char(&mut chars, ' ')?;
+    let en_passant = parse_en_passant(&mut chars, to_move)?;
+    let (reversable_moves, full_moves) = if options.lenient && chars.at_end() {
+        (0, 1)
+    } else {
+        parse_char(&mut chars, ' ')?;
+        let reversable_moves = parse_num(&mut chars, 0..=u16::MAX as u32)? as u16;
+        if options.lenient && chars.at_end() {
+            (reversable_moves, 1)
+        } else {
+            parse_char(&mut chars, ' ')?;
+            // Games start on move 1
+            (
+                reversable_moves,
+                parse_num(&mut chars, 1..=u16::MAX as u32)? as u16,
+            )
+        }
+    };
+    if !chars.at_end() {
+        chars.next()?;
+        return Err(chars.error(FenErrorKind::TrailingCharacters));
+    }
+
+    fn parse_line(chars: &mut FenCursor, ps: &mut BbPieceState, r: Rank) -> Result<(), FenError> {
         let mut square_ct: u8 = 0;
         while square_ct < 8 {
+            if matches!(chars.peek(), Some('/') | Some(' ')) {
+                chars.next()?;
+                return Err(chars.error(FenErrorKind::TooFewSquares(r)));
+            }
             let c = chars.next()?;
+            let bit = 1 << (r as u8 * 8 + square_ct);
             match c {
-                '1'..='9' => square_ct += c.to_string().parse::<u8>().unwrap(),
-                'P' => ps.wp.v |= (1 << square_ct) << (r as u8 * 8),
-                'R' => ps.wr.v |= (1 << square_ct) << (r as u8 * 8),
-                'N' => ps.wn.v |= (1 << square_ct) << (r as u8 * 8),
-                'B' => ps.wb.v |= (1 << square_ct) << (r as u8 * 8),
-                'Q' => ps.wq.v |= (1 << square_ct) << (r as u8 * 8),
-                'K' => ps.wk.v |= (1 << square_ct) << (r as u8 * 8),
-                'p' => ps.bp.v |= (1 << square_ct) << (r as u8 * 8),
-                'r' => ps.br.v |= (1 << square_ct) << (r as u8 * 8),
-                'n' => ps.bn.v |= (1 << square_ct) << (r as u8 * 8),
-                'b' => ps.bb.v |= (1 << square_ct) << (r as u8 * 8),
-                'q' => ps.bq.v |= (1 << square_ct) << (r as u8 * 8),
-                'k' => ps.bk.v |= (1 << square_ct) << (r as u8 * 8),
-                _ => return None,
+                '1'..='8' => square_ct += c as u8 - b'0',
+                'P' => ps.wp.v |= bit,
+                'R' => ps.wr.v |= bit,
+                'N' => ps.wn.v |= bit,
+                'B' => ps.wb.v |= bit,
+                'Q' => ps.wq.v |= bit,
+                'K' => ps.wk.v |= bit,
+                'p' => ps.bp.v |= bit,
+                'r' => ps.br.v |= bit,
+                'n' => ps.bn.v |= bit,
+                'b' => ps.bb.v |= bit,
+                'q' => ps.bq.v |= bit,
+                'k' => ps.bk.v |= bit,
+                _ => return Err(chars.error(FenErrorKind::BadPiece(c))),
             }
-            if !c.is_numeric() {
+            if !c.is_ascii_digit() {
                 square_ct += 1;
             }
         }
-        if square_ct == 8 {
-            Some(())
-        } else {
-            None
+        if square_ct > 8 {
+            return Err(chars.error(FenErrorKind::TooManySquares(r)));
+        }
+        match chars.peek() {
+            Some('/') | Some(' ') | None => Ok(()),
+            Some(_) => {
+                chars.next()?;
+                Err(chars.error(FenErrorKind::TooManySquares(r)))
+            }
         }
     }
 
-    fn parse_char(chars: &mut Chars, char: &char) -> Option<()> {
-        chars.next().filter(|x| x == char).map(|_x| ())
+    fn parse_char(chars: &mut FenCursor, expected: char) -> Result<(), FenError> {
+        let c = chars.next()?;
+        if c == expected {
+            Ok(())
+        } else {
+            Err(chars.error(FenErrorKind::Expected(expected, c)))
+        }
     }
 
-    fn parse_to_move(chars: &mut Chars) -> Option<Side> {
+    fn parse_to_move(chars: &mut FenCursor) -> Result<Side, FenError> {
         match chars.next()? {
-            'w' => Some(Side::White),
-            'b' => Some(Side::Black),
-            _ => None,
+            'w' => Ok(Side::White),
+            'b' => Ok(Side::Black),
+            c => Err(chars.error(FenErrorKind::BadSideToMove(c))),
         }
     }
 
-    fn parse_castling(chars: &mut Chars) -> Option<(bool, bool, bool, bool)> {
-        let mut c = chars.next()?;
+    // Standard `KQkq`, X-FEN and Shredder-FEN. `K` and `Q` mean the outermost rook on
+    // that side of the king, a file letter names the rook directly.
+    fn parse_castling(
+        chars: &mut FenCursor,
+        pieces: &BbPieceState,
+    ) -> Result<CastlingRights, FenError> {
+        let mut rights = CastlingRights::NONE;
+        let c = chars.next()?;
         if c == '-' {
-            Some((false, false, false, false))
-        } else if c == ' ' {
-            None
-        } else {
-            let mut w_kingside_castling = false;
-            let mut w_queenside_castling = false;
-            let mut b_kingside_castling = false;
-            let mut b_queenside_castling = false;
-
-            while c != ' ' {
-                match c {
-                    'K' => w_kingside_castling = true,
-                    'Q' => w_queenside_castling = true,
-                    'k' => b_kingside_castling = true,
-                    'q' => b_queenside_castling = true,
-                    _ => return None,
+            return Ok(rights);
+        }
+
+        let mut c = Some(c);
+        while let Some(x) = c {
+            let side = if x.is_ascii_uppercase() {
+                Side::White
+            } else {
+                Side::Black
+            };
+            let (wing, rook) = match x.to_ascii_lowercase() {
+                'k' => (
+                    BoardSide::KingSide,
+                    pieces
+                        .outermost_rook(side, BoardSide::KingSide)
+                        .unwrap_or(File::H),
+                ),
+                'q' => (
+                    BoardSide::QueenSide,
+                    pieces
+                        .outermost_rook(side, BoardSide::QueenSide)
+                        .unwrap_or(File::A),
+                ),
+                'a'..='h' => {
+                    let rook = File::from_char(x).unwrap();
+                    (pieces.castling_wing(side, rook), rook)
                 }
-                c = chars.next()?;
+                _ => return Err(chars.error(FenErrorKind::BadCastling(x))),
+            };
+            // Each right may only be given once
+            if rights.get(side, wing).is_some() {
+                return Err(chars.error(FenErrorKind::BadCastling(x)));
             }
-            Some((
-                w_kingside_castling,
-                w_queenside_castling,
-                b_kingside_castling,
-                b_queenside_castling,
-            ))
+            rights.set(side, wing, Some(rook));
+            c = match chars.peek() {
+                Some(' ') | None => None,
+                Some(_) => Some(chars.next()?),
+            };
         }
+        Ok(rights)
     }
 
-    fn parse_en_passant(chars: &mut Chars) -> Option<Option<File>> {
+    // The target square must be on the rank a pawn of the other side just passed over
+    fn parse_en_passant(chars: &mut FenCursor, to_move: Side) -> Result<Option<Square>, FenError> {
         let c = chars.next()?;
-        match c {
-            '-' => Some(None),
-            'a'..='e' => {
-                let c = chars.next()?;
-                if '1' <= c && c <= '8' {
-                    Some(File::from_char(c))
-                } else {
-                    None
-                }
-            }
+        if c == '-' {
+            return Ok(None);
+        }
+        let file = match c {
+            'a'..='h' => File::from_char(c),
             _ => None,
         }
+        .ok_or_else(|| chars.error(FenErrorKind::BadEnPassant(c)))?;
+        let (rank, expected) = match to_move {
+            Side::White => (Rank::R6, '6'),
+            Side::Black => (Rank::R3, '3'),
+        };
+        let c = chars.next()?;
+        if c == expected {
+            Ok(Some(Square::from_rank_file(rank, file)))
+        } else {
+            Err(chars.error(FenErrorKind::BadEnPassant(c)))
+        }
     }
 
-    fn parse_num(chars: &mut Chars) -> Option<u8> {
-        let mut c = chars.next();
-        let mut x = 0;
-        if !c.is_some_and(|x| x.is_ascii_digit()) {
-            return None;
+    fn parse_num(chars: &mut FenCursor, range: RangeInclusive<u32>) -> Result<u32, FenError> {
+        let start = chars.offset;
+        let mut x: u32 = 0;
+        let mut digits = 0;
+        while let Some(c) = chars.peek().filter(|c| *c != ' ') {
+            chars.next()?;
+            let d = c
+                .to_digit(10)
+                .ok_or_else(|| chars.error(FenErrorKind::BadCounter(c)))?;
+            x = x.saturating_mul(10).saturating_add(d);
+            digits += 1;
         }
-        while c.is_some_and(|x| x.is_ascii_digit()) {
-            x = x * 10 + c?.to_digit(10)?;
-            c = chars.next();
+        if digits == 0 {
+            let c = chars.next()?;
+            return Err(chars.error(FenErrorKind::BadCounter(c)));
+        }
+        if !range.contains(&x) {
+            return Err(FenError {
+                offset: start,
+                kind: FenErrorKind::CounterOutOfRange(x),
+            });
         }
-        Some(x as u8)
+        Ok(x)
     }
 
-    Some(BbBoardState {
-        pieces: pieces,
-        to_move: to_move,
-        en_passant: en_passant,
-        reversable_moves: reversable_moves,
-        w_kingside_castling: w_kingside_castling,
-        w_queenside_castling: w_queenside_castling,
-        b_kingside_castling: b_kingside_castling,
-        b_queenside_castling: b_queenside_castling,
-    })
+    let mut board = BbBoardState {
+        pieces,
+        to_move,
+        en_passant,
+        reversable_moves,
+        full_moves,
+        castling,
+        hash: 0,
+    };
+    if options.legal_en_passant_only && !board.has_legal_en_passant() {
+        board.en_passant = None;
+    }
+    board.hash = board.compute_hash();
+    Ok(board)
+}
+
+fn piece_char(side: Side, piece: Piece) -> char {
+    let c = match piece {
+        Piece::P => 'p',
+        Piece::N => 'n',
+        Piece::B => 'b',
+        Piece::R => 'r',
+        Piece::Q => 'q',
+        Piece::K => 'k',
+    };
+    match side {
+        Side::White => c.to_ascii_uppercase(),
+        Side::Black => c,
+    }
+}
+
+impl BbBoardState {
+    // Canonical FEN: castling rights in KQkq order, `-` for empty fields. Chess960
+    // rights use X-FEN, with a file letter only where `K` or `Q` would name the
+    // wrong rook.
+    pub fn to_fen(&self) -> String {
+        self.fen_with(false)
+    }
+
+    // Castling rights always as rook files, `HAha` for the standard position
+    pub fn to_shredder_fen(&self) -> String {
+        self.fen_with(true)
+    }
+
+    fn fen_with(&self, shredder: bool) -> String {
+        let mut s = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.pieces.piece_at(Square::new(rank * 8 + file)) {
+                    Some((side, piece)) => {
+                        if empty > 0 {
+                            s.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        s.push(piece_char(side, piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                s.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                s.push('/');
+            }
+        }
+
+        s.push(' ');
+        s.push(match self.to_move {
+            Side::White => 'w',
+            Side::Black => 'b',
+        });
+
+        s.push(' ');
+        for (side, wing, rook) in self.castling.iter() {
+            let c = if !shredder && self.pieces.outermost_rook(side, wing) == Some(rook) {
+                match wing {
+                    BoardSide::KingSide => 'k',
+                    BoardSide::QueenSide => 'q',
+                }
+            } else {
+                rook.to_char()
+            };
+            s.push(match side {
+                Side::White => c.to_ascii_uppercase(),
+                Side::Black => c,
+            });
+        }
+        if self.castling.is_empty() {
+            s.push('-');
+        }
+
+        s.push(' ');
+        match self.en_passant {
+            Some(ep) => s.push_str(&ep.to_string()),
+            None => s.push('-'),
+        }
+
+        format!("{} {} {}", s, self.reversable_moves, self.full_moves)
+    }
 }
 
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
@@ -351,6 +907,33 @@ pub struct Bitboard {
 
 type RankBits = u8;
 
+const RANK_1: u64 = 0xff;
+const FILE_A: u64 = 0x0101010101010101;
+const DIAGONAL: u64 = 0x8040201008040201;
+const ANTI_DIAGONAL: u64 = 0x0102040810204080;
+
+// Files that would wrap when shifting east or west by the index
+const FILE_H_WRAP: [u64; 8] = [
+    0,
+    0x8080808080808080,
+    0xc0c0c0c0c0c0c0c0,
+    0xe0e0e0e0e0e0e0e0,
+    0xf0f0f0f0f0f0f0f0,
+    0xf8f8f8f8f8f8f8f8,
+    0xfcfcfcfcfcfcfcfc,
+    0xfefefefefefefefe,
+];
+const FILE_A_WRAP: [u64; 8] = [
+    0,
+    0x0101010101010101,
+    0x0303030303030303,
+    0x0707070707070707,
+    0x0f0f0f0f0f0f0f0f,
+    0x1f1f1f1f1f1f1f1f,
+    0x3f3f3f3f3f3f3f3f,
+    0x7f7f7f7f7f7f7f7f,
+];
+
 impl Bitboard {
     pub const fn new(v: u64) -> Bitboard {
         Bitboard { v }
@@ -364,35 +947,85 @@ impl Bitboard {
         Bitboard { v: !0 }
     }
 
-    const fn rank(r: Rank) -> Bitboard {
-        todo!()
+    pub const fn rank(r: Rank) -> Bitboard {
+        Bitboard::new(RANK_1 << (r as u8 * 8))
+    }
+
+    pub const fn file(f: File) -> Bitboard {
+        Bitboard::new(FILE_A << f as u8)
     }
 
-    const fn file(f: File) -> Bitboard {
-        todo!()
+    // The a1-h8 direction diagonal through `s`
+    pub const fn diagonal(s: Square) -> Bitboard {
+        let rank = s.rank_index() as i8;
+        let file = s.file_index() as i8;
+        if rank >= file {
+            Bitboard::new(DIAGONAL << ((rank - file) * 8))
+        } else {
+            Bitboard::new(DIAGONAL >> ((file - rank) * 8))
+        }
     }
 
-    const fn shift_h(&self) -> Bitboard {
-        todo!()
+    // The a8-h1 direction diagonal through `s`
+    pub const fn anti_diagonal(s: Square) -> Bitboard {
+        let sum = (s.rank_index() + s.file_index()) as i8;
+        if sum >= 7 {
+            Bitboard::new(ANTI_DIAGONAL << ((sum - 7) * 8))
+        } else {
+            Bitboard::new(ANTI_DIAGONAL >> ((7 - sum) * 8))
+        }
     }
 
-    const fn shift_v(&self) -> Bitboard {
-        todo!()
+    // Move every square `n` files east, or west when negative. Squares pushed past
+    // the A or H file are dropped instead of wrapping onto the next rank.
+    pub const fn shift_h(&self, n: i8) -> Bitboard {
+        if n >= 8 || n <= -8 {
+            Bitboard::empty()
+        } else if n >= 0 {
+            Bitboard::new((self.v & !(FILE_H_WRAP[n as usize])) << n)
+        } else {
+            Bitboard::new((self.v & !(FILE_A_WRAP[(-n) as usize])) >> -n)
+        }
     }
 
-    const fn get_coord(s: Square) -> Bitboard {
+    // Move every square `n` ranks north, or south when negative
+    pub const fn shift_v(&self, n: i8) -> Bitboard {
+        if n >= 8 || n <= -8 {
+            Bitboard::empty()
+        } else if n >= 0 {
+            Bitboard::new(self.v << (n * 8))
+        } else {
+            Bitboard::new(self.v >> (-n * 8))
+        }
+    }
+
+    pub const fn shift(&self, d: Direction) -> Bitboard {
+        let (dr, df) = d.step();
+        self.shift_v(dr).shift_h(df)
+    }
+
+    pub const fn get_coord(s: Square) -> Bitboard {
         Bitboard::new(1 << s.v)
     }
 
+    pub const fn is_empty(&self) -> bool {
+        self.v == 0
+    }
+
+    // Lowest set square. The bitboard must not be empty.
+    pub const fn lsb(&self) -> Square {
+        Square::new(self.v.trailing_zeros() as u8)
+    }
+
     pub fn count_bits(&self) -> u8 {
         count_bits(self.v)
     }
 
-    const fn is_subset(&self, rhs: Bitboard) -> bool {
+    pub const fn is_subset(&self, rhs: Bitboard) -> bool {
         self.v & rhs.v == self.v
     }
 
-    const fn is_disjoint(&self, rhs: Bitboard) -> bool {
+    pub const fn is_disjoint(&self, rhs: Bitboard) -> bool {
         self.v & rhs.v == 0
     }
 
@@ -440,6 +1073,33 @@ impl Bitboard {
     }
 }
 
+pub struct BitboardIter {
+    v: u64,
+}
+
+impl Iterator for BitboardIter {
+    type Item = Square;
+
+    fn next(&mut self) -> Option<Square> {
+        if self.v == 0 {
+            None
+        } else {
+            let s = Square::new(self.v.trailing_zeros() as u8);
+            self.v &= self.v - 1;
+            Some(s)
+        }
+    }
+}
+
+impl IntoIterator for Bitboard {
+    type Item = Square;
+    type IntoIter = BitboardIter;
+
+    fn into_iter(self) -> BitboardIter {
+        BitboardIter { v: self.v }
+    }
+}
+
 impl Binary for Bitboard {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         std::fmt::Binary::fmt(&self.v, f)
@@ -566,23 +1226,16 @@ impl Not for Bitboard {
     }
 }
 
-//// Convert (North, 8) to Movement Squares
-//fn direction_to_move((s1, direction, dist): Compass) -> Square {
-//    if (direction == Equal) {
-//        return Bitboard ;
-//    }
-//
-//    let x = match direction {
-//        N => 8,
-//        NE => 9,
-//        E => 1,
-//        SE => -7,
-//        S => -8,
-//        SW => -9,
-//        W => -1,
-//        Equal => 0,
-//    };
-//    s + x * dist
+// Convert (North, 8) to the square reached by moving that far. Empty if it leaves the board.
+pub const fn direction_to_move((s, direction, dist): Compass) -> Bitboard {
+    // Off the board from anywhere, and too far to fit an i8 shift
+    if dist >= 8 {
+        return Bitboard::empty();
+    }
+    let (dr, df) = direction.step();
+    let dist = dist as i8;
+    Bitboard::get_coord(s).shift_v(dr * dist).shift_h(df * dist)
+}
 
 #[cfg(test)]
 mod tests {
@@ -695,6 +1348,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rank_and_file_masks() {
+        assert!(Bitboard::rank(Rank::R1).v == 0xff);
+        assert!(Bitboard::rank(Rank::R8).v == 0xff00000000000000);
+        assert!(Bitboard::file(File::A).v == 0x0101010101010101);
+        assert!(Bitboard::file(File::H).v == 0x8080808080808080);
+    }
+
+    #[test]
+    fn diagonal_masks() {
+        // a1, h1, d4 and e4
+        assert!(Bitboard::diagonal(Square::new(0)).v == DIAGONAL);
+        assert!(Bitboard::diagonal(Square::new(7)).v == 1 << 7);
+        assert!(Bitboard::diagonal(Square::new(27)).v == DIAGONAL);
+        assert!(Bitboard::anti_diagonal(Square::new(7)).v == ANTI_DIAGONAL);
+        assert!(Bitboard::anti_diagonal(Square::new(0)).v == 1);
+        assert!(Bitboard::anti_diagonal(Square::new(28)).v == ANTI_DIAGONAL);
+        // b1 lies on the diagonal b1-h7
+        assert!(Bitboard::diagonal(Square::new(1)).count_bits() == 7);
+    }
+
+    #[test]
+    fn shifts_do_not_wrap() {
+        let h_file = Bitboard::file(File::H);
+        let a_file = Bitboard::file(File::A);
+        assert!(h_file.shift(Direction::E).is_empty());
+        assert!(h_file.shift(Direction::NE).is_empty());
+        assert!(a_file.shift(Direction::W).is_empty());
+        assert!(a_file.shift(Direction::SW).is_empty());
+        assert!(a_file.shift(Direction::E) == Bitboard::file(File::B));
+        assert!(Bitboard::rank(Rank::R8).shift(Direction::N).is_empty());
+        assert!(Bitboard::rank(Rank::R1).shift(Direction::S).is_empty());
+        assert!(Bitboard::full().shift_h(3).v == !0x0707070707070707);
+        assert!(Bitboard::full().shift_h(-3).v == !0xe0e0e0e0e0e0e0e0);
+    }
+
+    #[test]
+    fn shifts_in_every_direction_from_the_centre() {
+        // d4 moved one step in each direction is the king's neighbourhood
+        let d4 = Bitboard::get_coord(Square::new(27));
+        let mut ring = Bitboard::empty();
+        for d in Direction::COMPASS {
+            ring |= d4.shift(d);
+            assert!(d4.shift(d).shift(d.opposite()) == d4);
+        }
+        assert!(ring.v == 0x1c141c0000);
+        assert!(d4.shift(Direction::Equal) == d4);
+    }
+
+    #[test]
+    fn direction_to_move_tests() {
+        let a1 = Square::new(0);
+        assert!(direction_to_move((a1, Direction::N, 7)).v == 1 << 56);
+        assert!(direction_to_move((a1, Direction::NE, 7)).v == 1 << 63);
+        assert!(direction_to_move((a1, Direction::W, 1)).is_empty());
+        assert!(direction_to_move((a1, Direction::E, 8)).is_empty());
+        let a8 = Square::new(56);
+        assert!(direction_to_move((a8, Direction::N, 250)).is_empty());
+        assert!(direction_to_move((a8, Direction::S, 128)).is_empty());
+    }
+
     #[test]
     fn bbps_sould_fail_on_empty() {
         let ps = BbPieceState::empty();
@@ -717,7 +1431,7 @@ mod tests {
             bq: Bitboard::new(0),
             bk: Bitboard::new(32),
         };
-        assert!(ps.is_legal());
+        assert!(!ps.is_legal());
     }
 
     #[test]
@@ -734,14 +1448,99 @@ mod tests {
         assert!(start.pretty_print() == expected)
     }
 
+    fn find_move(board: &BbBoardState, from: u8, to: u8) -> Move {
+        *board
+            .legal_moves()
+            .iter()
+            .find(|m| m.from().v == from && m.to().v == to)
+            .unwrap()
+    }
+
+    // Make and unmake every move down to `depth`, checking the position is restored
+    fn make_unmake_all(board: &mut BbBoardState, depth: u8) {
+        if depth == 0 {
+            return;
+        }
+        for m in board.legal_moves() {
+            let before = board.clone();
+            let undo = board.make_move(m);
+            make_unmake_all(board, depth - 1);
+            board.unmake_move(m, undo);
+            assert!(*board == before, "{:?}", m);
+        }
+    }
+
+    #[test]
+    fn make_unmake_restores_kiwipete() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        make_unmake_all(&mut parse_fen(fen.to_string()).unwrap(), 2);
+    }
+
+    #[test]
+    fn make_unmake_restores_promotions() {
+        let fen = "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1";
+        make_unmake_all(&mut parse_fen(fen.to_string()).unwrap(), 2);
+    }
+
+    #[test]
+    fn make_double_push_sets_en_passant() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let m = find_move(&board, 12, 28);
+        board.make_move(m);
+        assert!(board.en_passant == Some(Square::from_rank_file(Rank::R3, File::E)));
+        assert!(board.reversable_moves == 0);
+        assert!(board.to_move == Side::Black);
+        let m = find_move(&board, 62, 45);
+        board.make_move(m);
+        assert!(board.en_passant.is_none());
+        assert!(board.reversable_moves == 1);
+    }
+
+    #[test]
+    fn make_en_passant_removes_pawn() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        board.en_passant = Some(Square::from_rank_file(Rank::R6, File::D));
+        let before = board.clone();
+        let m = find_move(&board, 36, 43);
+        let undo = board.make_move(m);
+        assert!(board.pieces.bp.is_empty());
+        assert!(board.pieces.wp.v == 1 << 43);
+        board.unmake_move(m, undo);
+        assert!(board == before);
+    }
+
+    #[test]
+    fn make_castling_moves_rook_and_clears_rights() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        // Castling is king takes own rook
+        let m = find_move(&board, 4, 7);
+        assert!(m.kind() == MoveType::CastleKing);
+        board.make_move(m);
+        assert!(board.pieces.wk.v == 1 << 6);
+        assert!(board.pieces.wr.v == (1 << 5) | 1);
+        let rights = board.castling;
+        assert!(rights.get(Side::White, BoardSide::KingSide).is_none());
+        assert!(rights.get(Side::White, BoardSide::QueenSide).is_none());
+        assert!(rights.get(Side::Black, BoardSide::KingSide) == Some(File::H));
+        assert!(rights.get(Side::Black, BoardSide::QueenSide) == Some(File::A));
+        let m = find_move(&board, 56, 0);
+        board.make_move(m);
+        assert!(board
+            .castling
+            .get(Side::Black, BoardSide::QueenSide)
+            .is_none());
+        assert!(board.castling.get(Side::Black, BoardSide::KingSide) == Some(File::H));
+    }
+
     #[test]
+    #[allow(clippy::partialeq_to_none)]
     fn fen_parse_start() {
         let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
         let start = parse_fen(fen.to_string()).unwrap();
-        assert!(start.w_kingside_castling == true);
-        assert!(start.w_queenside_castling == true);
-        assert!(start.b_kingside_castling == true);
-        assert!(start.b_queenside_castling == true);
+        assert!(start.castling == CastlingRights::STANDARD);
         assert!(start.en_passant == None);
         assert!(start.reversable_moves == 0);
         assert!(start.to_move == Side::White);
@@ -750,13 +1549,250 @@ mod tests {
     }
 
     #[test]
+    fn fen_parse_keeps_full_moves() {
+        let fen = "rnbqkbnr/pp2pppp/3p4/2p5/3PP3/5N2/PPP2PPP/RNBQKB1R b KQkq - 0 3";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.full_moves == 3);
+        let m = board.legal_moves()[0];
+        let undo = board.make_move(m);
+        assert!(board.full_moves == 4);
+        let m2 = board.legal_moves()[0];
+        let undo2 = board.make_move(m2);
+        assert!(board.full_moves == 4);
+        board.unmake_move(m2, undo2);
+        board.unmake_move(m, undo);
+        assert!(board.full_moves == 3);
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pp2pppp/3p4/2p5/3PP3/5N2/PPP2PPP/RNBQKB1R b KQkq - 0 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "4k3/8/8/8/8/8/8/4K2R w K - 17 42",
+            "8/8/8/8/8/8/8/8 b - - 0 1",
+        ];
+        for fen in fens {
+            let board = parse_fen(fen.to_string()).unwrap();
+            assert!(board.to_fen() == fen, "{} became {}", fen, board.to_fen());
+        }
+    }
+
+    #[test]
+    fn fen_round_trip_after_moves() {
+        // Walk the tree and check every position survives writing and reading back
+        fn walk(board: &mut BbBoardState, depth: u8) {
+            let fen = board.to_fen();
+            let parsed = parse_fen(fen.clone()).unwrap();
+            assert!(parsed == *board, "{}", fen);
+            assert!(parsed.to_fen() == fen);
+            if depth == 0 {
+                return;
+            }
+            for m in board.legal_moves() {
+                let undo = board.make_move(m);
+                walk(board, depth - 1);
+                board.unmake_move(m, undo);
+            }
+        }
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        walk(&mut parse_fen(fen.to_string()).unwrap(), 2);
+    }
+
+    #[test]
+    fn to_fen_writes_en_passant_square() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let m = find_move(&board, 12, 28);
+        board.make_move(m);
+        let expected = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert!(board.to_fen() == expected);
+    }
+
+    fn fen_error(fen: &str) -> FenError {
+        parse_fen(fen.to_string()).unwrap_err()
+    }
+
+    #[test]
+    fn fen_error_bad_piece() {
+        let e = fen_error("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(e.kind == FenErrorKind::BadPiece('x'));
+        assert!(e.offset == 13);
+        let e = fen_error("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(e.kind == FenErrorKind::BadPiece('9'));
+    }
+
+    #[test]
+    fn fen_error_rank_length() {
+        let e = fen_error("rnbqkbnrr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(e.kind == FenErrorKind::TooManySquares(Rank::R8));
+        assert!(e.offset == 8);
+        let e = fen_error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKB2R w KQkq - 0 1");
+        assert!(e.kind == FenErrorKind::TooManySquares(Rank::R1));
+        let e = fen_error("rnbqkbnr/pppppppp/8/7/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(e.kind == FenErrorKind::TooFewSquares(Rank::R5));
+        assert!(e.offset == 21);
+    }
+
+    #[test]
+    fn fen_error_fields() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+        let e = fen_error(&format!("{} x KQkq - 0 1", start));
+        assert!(e.kind == FenErrorKind::BadSideToMove('x'));
+        assert!(e.offset == 44);
+        let e = fen_error(&format!("{} w KXkq - 0 1", start));
+        assert!(e.kind == FenErrorKind::BadCastling('X'));
+        assert!(e.offset == 47);
+        let e = fen_error(&format!("{} w KKkq - 0 1", start));
+        assert!(e.kind == FenErrorKind::BadCastling('K'));
+        let e = fen_error(&format!("{} w KQkq z3 0 1", start));
+        assert!(e.kind == FenErrorKind::BadEnPassant('z'));
+        let e = fen_error(&format!("{} w KQkq - x 1", start));
+        assert!(e.kind == FenErrorKind::BadCounter('x'));
+        assert!(e.offset == 53);
+        let e = fen_error(&format!("{} w KQkq - 70000 1", start));
+        assert!(e.kind == FenErrorKind::CounterOutOfRange(70000));
+        assert!(e.offset == 53);
+        let e = fen_error(&format!("{} w KQkq - 0 99999999999", start));
+        assert!(e.kind == FenErrorKind::CounterOutOfRange(u32::MAX));
+        let e = fen_error(&format!("{} w KQkq - 0 0", start));
+        assert!(e.kind == FenErrorKind::CounterOutOfRange(0));
+        assert!(e.offset == 55);
+        let e = fen_error(&format!("{} w KQkq - 0 1 x", start));
+        assert!(e.kind == FenErrorKind::TrailingCharacters);
+        let e = fen_error(&format!("{} w KQkq -  0 1", start));
+        assert!(e.kind == FenErrorKind::BadCounter(' '));
+    }
+
+    #[test]
+    fn counters_keep_full_width() {
+        let fen = "8/8/4k3/8/8/4K3/8/8 w - - 300 1000";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.halfmove_clock() == 300 && board.fullmove_number() == 1000);
+        assert!(board.to_fen() == fen);
+
+        let m = board.legal_moves()[0];
+        let undo = board.make_move(m);
+        assert!(board.halfmove_clock() == 301 && board.fullmove_number() == 1000);
+        let reply = board.legal_moves()[0];
+        let reply_undo = board.make_move(reply);
+        assert!(board.halfmove_clock() == 302 && board.fullmove_number() == 1001);
+        board.unmake_move(reply, reply_undo);
+        board.unmake_move(m, undo);
+        assert!(board.to_fen() == fen);
+    }
+
+    #[test]
+    fn fifty_and_seventy_five_move_rules() {
+        let draw = |clock: u16| {
+            let fen = format!("8/8/4k3/8/8/4K3/8/8 w - - {} 80", clock);
+            let board = parse_fen(fen).unwrap();
+            (
+                board.is_fifty_move_draw(),
+                board.is_seventy_five_move_draw(),
+            )
+        };
+        assert!(draw(99) == (false, false));
+        assert!(draw(100) == (true, false));
+        assert!(draw(149) == (true, false));
+        assert!(draw(150) == (true, true));
+
+        // Mate on the last move takes precedence
+        let fen = "7k/6Q1/6K1/8/8/8/8/8 b - - 150 100";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(!board.is_fifty_move_draw() && !board.is_seventy_five_move_draw());
+
+        // A capture or pawn move resets the clock
+        let fen = "8/8/4k3/8/8/4K3/3P4/8 w - - 120 80";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let push = *board
+            .legal_moves()
+            .iter()
+            .find(|m| m.from().v == 11)
+            .unwrap();
+        board.make_move(push);
+        assert!(board.halfmove_clock() == 0 && !board.is_fifty_move_draw());
+    }
+
+    #[test]
+    fn fen_error_display_has_offset() {
+        let e = fen_error("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(e.to_string() == "'x' is not a piece at offset 13");
+    }
+
+    #[test]
+    fn fen_en_passant_square() {
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.en_passant == Some(Square::from_rank_file(Rank::R6, File::F)));
+        assert!(board.to_fen() == fen);
+
+        let fen = "rnbqkbnr/pppppppp/8/8/7P/8/PPPPPPP1/RNBQKBNR b KQkq h3 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.en_passant == Some(Square::from_rank_file(Rank::R3, File::H)));
+
+        // Wrong rank for the side to move
+        let fen = "rnbqkbnr/pppppppp/8/8/7P/8/PPPPPPP1/RNBQKBNR b KQkq h6 0 1";
+        let e = fen_error(fen);
+        assert!(e.kind == FenErrorKind::BadEnPassant('6'));
+        assert!(e.offset == 53);
+        let fen = "rnbqkbnr/pppppppp/8/8/7P/8/PPPPPPP1/RNBQKBNR b KQkq i3 0 1";
+        assert!(fen_error(fen).kind == FenErrorKind::BadEnPassant('i'));
+    }
+
+    #[test]
+    fn fen_legal_en_passant_only() {
+        let options = FenOptions {
+            legal_en_passant_only: true,
+            ..Default::default()
+        };
+        // Nothing can take on e3
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert!(parse_fen(fen.to_string()).unwrap().en_passant.is_some());
+        let board = parse_fen_with(fen.to_string(), options).unwrap();
+        assert!(board.en_passant.is_none());
+        assert!(board.hash() == board.compute_hash());
+
+        // exf6 is possible
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        let board = parse_fen_with(fen.to_string(), options).unwrap();
+        assert!(board.en_passant.is_some());
+
+        // The capture would expose the king on the fifth rank
+        let fen = "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1";
+        let board = parse_fen_with(fen.to_string(), options).unwrap();
+        assert!(board.en_passant.is_none());
+    }
+
+    #[test]
+    fn fen_missing_counters() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+        let e = fen_error(fen);
+        assert!(e.kind == FenErrorKind::UnexpectedEnd);
+        assert!(e.offset == fen.len());
+
+        let board = parse_fen_lenient(fen.to_string()).unwrap();
+        assert!(board.reversable_moves == 0);
+        assert!(board.full_moves == 1);
+        assert!(board.to_fen() == format!("{} 0 1", fen));
+
+        let board = parse_fen_lenient(format!("  {} 7\n", fen)).unwrap();
+        assert!(board.reversable_moves == 7);
+        assert!(board.full_moves == 1);
+        assert!(parse_fen_lenient(format!("{} 0 1 x", fen)).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::partialeq_to_none)]
     fn fen_parse_game_1() {
         let fen = "rnbqkbnr/pp2pppp/3p4/2p5/3PP3/5N2/PPP2PPP/RNBQKB1R b KQkq - 0 3";
         let game = parse_fen(fen.to_string()).unwrap();
-        assert!(game.w_kingside_castling == true);
-        assert!(game.w_queenside_castling == true);
-        assert!(game.b_kingside_castling == true);
-        assert!(game.b_queenside_castling == true);
+        assert!(game.castling == CastlingRights::STANDARD);
         assert!(game.en_passant == None);
         assert!(game.reversable_moves == 0);
         assert!(game.to_move == Side::Black);
@@ -0,0 +1,137 @@
+use crate::api::{BoardSide, File, Piece, Side, Square};
+use crate::bitboard::{BbPieceState, Bitboard};
+
+// The file of the rook each side may still castle with. Storing files rather
+// than flags lets the king and rooks start anywhere on the back rank, as in Chess960.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CastlingRights {
+    // Indexed by `Side as usize`, then `BoardSide as usize`
+    rooks: [[Option<File>; 2]; 2],
+}
+
+pub const WINGS: [BoardSide; 2] = [BoardSide::KingSide, BoardSide::QueenSide];
+
+impl CastlingRights {
+    pub const NONE: CastlingRights = CastlingRights {
+        rooks: [[None; 2]; 2],
+    };
+
+    // All four rights with rooks on a and h
+    pub const STANDARD: CastlingRights = CastlingRights {
+        rooks: [[Some(File::A), Some(File::H)]; 2],
+    };
+
+    pub fn get(&self, side: Side, wing: BoardSide) -> Option<File> {
+        self.rooks[side as usize][wing as usize]
+    }
+
+    pub fn set(&mut self, side: Side, wing: BoardSide, rook: Option<File>) {
+        self.rooks[side as usize][wing as usize] = rook;
+    }
+
+    pub fn clear_side(&mut self, side: Side) {
+        self.rooks[side as usize] = [None; 2];
+    }
+
+    // Drop the right, if any, whose rook starts on `s`
+    pub fn clear_rook(&mut self, s: Square) {
+        for side in [Side::White, Side::Black] {
+            if s.rank_index() != side.back_rank() as u8 {
+                continue;
+            }
+            for rook in self.rooks[side as usize].iter_mut() {
+                if rook.is_some_and(|f| f as u8 == s.file_index()) {
+                    *rook = None;
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == CastlingRights::NONE
+    }
+
+    // Rights held, white first and kingside before queenside
+    pub fn iter(&self) -> impl Iterator<Item = (Side, BoardSide, File)> + '_ {
+        [Side::White, Side::Black]
+            .into_iter()
+            .flat_map(move |side| {
+                WINGS
+                    .into_iter()
+                    .filter_map(move |wing| self.get(side, wing).map(|f| (side, wing, f)))
+            })
+    }
+}
+
+impl BbPieceState {
+    // File of the king if it stands on its back rank, where it has to be to castle
+    pub fn castling_king_file(&self, side: Side) -> Option<File> {
+        let kings = self.get(side, Piece::K) & Bitboard::rank(side.back_rank());
+        match kings.count_bits() {
+            1 => File::from_index(kings.lsb().file_index()),
+            _ => None,
+        }
+    }
+
+    // The rook an X-FEN `K` or `Q` refers to: the outermost one on that side of the king
+    pub fn outermost_rook(&self, side: Side, wing: BoardSide) -> Option<File> {
+        let king = self.castling_king_file(side).unwrap_or(File::E) as u8;
+        let rooks = (self.get(side, Piece::R) & Bitboard::rank(side.back_rank()))
+            .into_iter()
+            .map(|s| s.file_index());
+        let file = match wing {
+            BoardSide::KingSide => rooks.filter(|&f| f > king).max(),
+            BoardSide::QueenSide => rooks.filter(|&f| f < king).min(),
+        };
+        file.and_then(File::from_index)
+    }
+
+    // Which wing a rook on `rook` is on, from where the king stands
+    pub fn castling_wing(&self, side: Side, rook: File) -> BoardSide {
+        let king = self.castling_king_file(side).unwrap_or(File::E);
+        if rook as u8 > king as u8 {
+            BoardSide::KingSide
+        } else {
+            BoardSide::QueenSide
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+    use crate::castling::*;
+
+    #[test]
+    fn clear_rook_only_touches_own_back_rank() {
+        let mut rights = CastlingRights::STANDARD;
+        // h8
+        rights.clear_rook(Square::new(63));
+        assert!(rights.get(Side::Black, BoardSide::KingSide).is_none());
+        assert!(rights.get(Side::White, BoardSide::KingSide) == Some(File::H));
+        // h4
+        rights.clear_rook(Square::new(31));
+        assert!(rights.get(Side::White, BoardSide::KingSide) == Some(File::H));
+        rights.clear_side(Side::White);
+        assert!(
+            rights.iter().collect::<Vec<_>>() == vec![(Side::Black, BoardSide::QueenSide, File::A)]
+        );
+        rights.clear_rook(Square::new(56));
+        assert!(rights.is_empty());
+    }
+
+    #[test]
+    fn outermost_rooks() {
+        let fen = "1r2k1rr/8/8/8/8/8/8/RR3K1R w - - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        let p = &board.pieces;
+        assert!(p.castling_king_file(Side::White) == Some(File::F));
+        assert!(p.outermost_rook(Side::White, BoardSide::QueenSide) == Some(File::A));
+        assert!(p.outermost_rook(Side::White, BoardSide::KingSide) == Some(File::H));
+        assert!(p.outermost_rook(Side::Black, BoardSide::QueenSide) == Some(File::B));
+        assert!(p.outermost_rook(Side::Black, BoardSide::KingSide) == Some(File::H));
+        assert!(p.castling_wing(Side::Black, File::G) == BoardSide::KingSide);
+        assert!(p.castling_wing(Side::White, File::B) == BoardSide::QueenSide);
+    }
+}
@@ -0,0 +1,411 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use crate::api::{Board, GameState, Move, Side};
+use crate::bitboard::{parse_fen, BbBoardState};
+use crate::engine::{Engine, Output};
+use crate::game::{DrawReason, Game, GameResult};
+use crate::search::{Limits, Score, SearchInfo};
+
+// Features asked for in reply to `protover 2`
+const FEATURES: &str = "feature myname=\"chess\" setboard=1 usermove=1 san=0 ping=1 \
+    colors=0 sigint=0 sigterm=0 variants=\"normal\" done=1";
+
+// XBoard's mate scores count up from this
+const MATE_SCORE: i32 = 100000;
+
+// The Chess Engine Communication Protocol used by XBoard. Unlike UCI the engine
+// keeps the game itself and decides when to move.
+pub struct Cecp {
+    engine: Engine,
+    out: Output,
+    // Side the engine plays, none in force mode
+    engine_side: Option<Side>,
+    // Moves found by the search, played on the next command
+    found: (Sender<Move>, Receiver<Move>),
+    post: bool,
+    // `level`: moves per time control (0 for all), base time and increment
+    moves_per_control: u32,
+    base: Duration,
+    inc: Duration,
+    // `st` and `sd`
+    movetime: Option<Duration>,
+    depth: Option<u32>,
+    // `time` and `otim`
+    clocks: [Option<Duration>; 2],
+}
+
+impl Cecp {
+    pub fn new(out: Output) -> Self {
+        Cecp {
+            engine: Engine::new(),
+            out,
+            engine_side: Some(Side::Black),
+            found: mpsc::channel(),
+            post: false,
+            moves_per_control: 0,
+            base: Duration::from_secs(300),
+            inc: Duration::ZERO,
+            movetime: None,
+            depth: None,
+            clocks: [None; 2],
+        }
+    }
+
+    // Handle one command. False once told to quit.
+    pub fn handle(&mut self, line: &str) -> bool {
+        self.play_found();
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return true,
+        };
+        let args: Vec<&str> = words.collect();
+        let arg = args.first().copied().unwrap_or("");
+        match cmd {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" => (),
+            "protover" => self.out.line(FEATURES),
+            "ping" => self.out.line(&format!("pong {}", arg)),
+            "new" => {
+                self.abort();
+                self.engine.game = Game::start();
+                self.engine_side = Some(Side::Black);
+                self.depth = None;
+                self.movetime = None;
+            }
+            "setboard" => {
+                self.abort();
+                match parse_fen(args.join(" ")) {
+                    Ok(board) => self.engine.game = Game::new(board),
+                    Err(e) => self
+                        .out
+                        .line(&format!("tellusererror Illegal position: {}", e)),
+                }
+            }
+            "force" => {
+                // A move already sent to the GUI still counts
+                self.engine.abort();
+                self.play_found();
+                self.engine_side = None;
+            }
+            "go" => {
+                self.engine_side = Some(self.engine.game.board().to_move);
+                self.think();
+            }
+            "playother" => {
+                self.engine_side = Some(self.engine.game.board().to_move.opposite());
+            }
+            // Move now
+            "?" => {
+                self.engine.stop();
+                self.play_found();
+            }
+            "usermove" => self.user_move(arg),
+            "level" => {
+                if let Err(e) = self.level(&args) {
+                    self.out.line(&format!("Error ({}): level", e));
+                }
+            }
+            "st" => match arg.parse::<f64>() {
+                Ok(secs) if secs >= 0.0 => self.movetime = Some(Duration::from_secs_f64(secs)),
+                _ => self.out.line("Error (bad time): st"),
+            },
+            "sd" => match arg.parse() {
+                Ok(depth) => self.depth = Some(depth),
+                Err(_) => self.out.line("Error (bad depth): sd"),
+            },
+            "time" | "otim" => {
+                // Centiseconds on the engine's and the opponent's clock
+                let ours = self.engine_side.unwrap_or(self.engine.game.board().to_move);
+                let side = if cmd == "time" { ours } else { ours.opposite() };
+                match arg.parse::<u64>() {
+                    Ok(cs) => self.clocks[side as usize] = Some(Duration::from_millis(cs * 10)),
+                    Err(_) => self.out.line(&format!("Error (bad time): {}", cmd)),
+                }
+            }
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "result" => {
+                self.abort();
+                self.engine_side = None;
+            }
+            "quit" => {
+                self.engine.abort();
+                return false;
+            }
+            // Protocol version 1 sends bare moves
+            _ if self.engine.game.board().parse_uci(cmd, false).is_ok() => self.user_move(cmd),
+            _ if self.engine.game.board().parse_san(cmd).is_ok() => self.user_move(cmd),
+            _ => self.out.line(&format!("Error (unknown command): {}", cmd)),
+        }
+        true
+    }
+
+    // Play the move the search came back with, if it hasn't been yet
+    fn play_found(&mut self) {
+        while let Ok(m) = self.found.1.try_recv() {
+            self.engine.game.play(m);
+        }
+    }
+
+    // Stop searching and drop any move found but not played yet, so it can't
+    // end up in the next game
+    fn abort(&mut self) {
+        self.engine.abort();
+        while self.found.1.try_recv().is_ok() {}
+    }
+
+    // A move in coordinate notation or SAN
+    fn user_move(&mut self, s: &str) {
+        self.engine.abort();
+        let board = self.engine.game.board();
+        let m = match board
+            .parse_uci(s, false)
+            .or_else(|_| board.parse_san(s).map_err(|_| ()))
+        {
+            Ok(m) => m,
+            Err(_) => return self.out.line(&format!("Illegal move: {}", s)),
+        };
+        self.engine.game.play(m);
+        match self.engine.game.result() {
+            Some(result) => self.out.line(&result_line(result)),
+            None => self.think(),
+        }
+    }
+
+    fn take_back(&mut self, plies: usize) {
+        self.abort();
+        for _ in 0..plies {
+            self.engine.game.takeback();
+        }
+    }
+
+    // level <moves> <minutes[:seconds]> <increment seconds>
+    fn level(&mut self, args: &[&str]) -> Result<(), &'static str> {
+        let [mps, base, inc] = args else {
+            return Err("expected three values");
+        };
+        self.moves_per_control = mps.parse().map_err(|_| "bad moves")?;
+        let (min, sec) = base.split_once(':').unwrap_or((base, "0"));
+        let min: u64 = min.parse().map_err(|_| "bad base time")?;
+        let sec: u64 = sec.parse().map_err(|_| "bad base time")?;
+        self.base = Duration::from_secs(min * 60 + sec);
+        let inc: f64 = inc.parse().map_err(|_| "bad increment")?;
+        self.inc = Duration::from_secs_f64(inc.max(0.0));
+        self.movetime = None;
+        Ok(())
+    }
+
+    fn limits(&self) -> Limits {
+        let board = self.engine.game.board();
+        let movestogo = match self.moves_per_control {
+            0 => None,
+            mps => Some(mps - (board.fullmove_number() as u32).saturating_sub(1) % mps),
+        };
+        Limits {
+            depth: self.depth,
+            movetime: self.movetime,
+            time: self.clocks.map(|c| c.or(Some(self.base))),
+            inc: [self.inc; 2],
+            movestogo,
+            ..Limits::default()
+        }
+    }
+
+    // Search if it's the engine's turn, then play and announce the move
+    fn think(&mut self) {
+        let board = self.engine.game.board();
+        if self.engine_side != Some(board.to_move) || self.engine.game.result().is_some() {
+            return;
+        }
+        let (info_out, done_out) = (self.out.clone(), self.out.clone());
+        let (post, root) = (self.post, board.clone());
+        let mut game = self.engine.game.clone();
+        let found = self.found.0.clone();
+        self.engine.go(
+            self.limits(),
+            move |info| {
+                if post {
+                    info_out.line(&thinking_line(info, &root));
+                }
+            },
+            move |m| {
+                let m = match m {
+                    Some(m) => m,
+                    None => return,
+                };
+                // Queued before the GUI can answer the move
+                let _ = found.send(m);
+                done_out.line(&format!("move {}", m));
+                game.play(m);
+                if let Some(result) = game.result() {
+                    done_out.line(&result_line(result));
+                }
+            },
+        );
+    }
+}
+
+// ply score time nodes pv, with the time in centiseconds and the pv in SAN
+fn thinking_line(info: &SearchInfo, root: &BbBoardState) -> String {
+    let score = match info.score {
+        Score::Cp(cp) => cp,
+        Score::Mate(n) if n > 0 => MATE_SCORE + n,
+        Score::Mate(n) => -MATE_SCORE + n,
+    };
+    let mut board = root.clone();
+    let pv: Vec<String> = info
+        .pv
+        .iter()
+        .map(|&m| {
+            let san = board.to_san(m);
+            board.make_move(m);
+            san
+        })
+        .collect();
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        score,
+        info.time.as_millis() / 10,
+        info.nodes,
+        pv.join(" ")
+    )
+}
+
+fn result_line(result: GameResult) -> String {
+    match result {
+        GameResult::Checkmate(Side::White) => "1-0 {White mates}".to_string(),
+        GameResult::Checkmate(Side::Black) => "0-1 {Black mates}".to_string(),
+        GameResult::Draw(reason) => {
+            let why = match reason {
+                DrawReason::Stalemate => "Stalemate",
+                DrawReason::InsufficientMaterial => "Insufficient material",
+                DrawReason::DeadPosition => "Dead position",
+                DrawReason::FivefoldRepetition => "Fivefold repetition",
+                DrawReason::SeventyFiveMoves => "75-move rule",
+                DrawReason::ThreefoldRepetition => "Threefold repetition",
+                DrawReason::FiftyMoves => "50-move rule",
+            };
+            format!("1/2-1/2 {{{}}}", why)
+        }
+    }
+}
+
+// Handle commands until `quit` or the end of the input
+pub fn run(lines: impl Iterator<Item = String>, out: Output) {
+    let mut cecp = Cecp::new(out);
+    for line in lines {
+        if !cecp.handle(&line) {
+            break;
+        }
+    }
+    cecp.engine.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cecp::*;
+    use crate::engine::*;
+
+    fn cecp() -> (Cecp, Buffer) {
+        let buffer = Buffer::default();
+        (Cecp::new(Output::new(buffer.clone())), buffer)
+    }
+
+    #[test]
+    fn feature_negotiation() {
+        let (mut cecp, out) = cecp();
+        cecp.handle("xboard");
+        cecp.handle("protover 2");
+        cecp.handle("ping 7");
+        let lines = out.lines();
+        assert!(lines[0].starts_with("feature ") && lines[0].ends_with("done=1"));
+        assert!(lines[1] == "pong 7");
+        assert!(!cecp.handle("quit"));
+    }
+
+    #[test]
+    fn engine_answers_user_moves() {
+        let (mut cecp, out) = cecp();
+        cecp.handle("new");
+        cecp.handle("sd 2");
+        cecp.handle("post");
+        cecp.handle("usermove e2e4");
+        cecp.engine.wait();
+        let lines = out.lines();
+        // Thinking output then the move, which starts the last pv
+        assert!(lines.len() == 3 && lines[0].starts_with("1 ") && lines[1].starts_with("2 "));
+        let reply = lines[2].strip_prefix("move ").unwrap().parse().unwrap();
+        let san = cecp.engine.game.board().to_san(reply);
+        assert!(lines[1].split(' ').nth(4) == Some(san.as_str()));
+
+        // The reply is played before the next command, which may be SAN
+        cecp.handle("force");
+        assert!(cecp.engine.game.ply() == 2);
+        cecp.handle("Nf3");
+        assert!(cecp.engine.game.ply() == 3);
+        cecp.handle("usermove e2e4");
+        assert!(out.lines().last().unwrap() == "Illegal move: e2e4");
+
+        cecp.handle("remove");
+        assert!(cecp.engine.game.ply() == 1);
+        cecp.handle("undo");
+        assert!(cecp.engine.game.ply() == 0);
+    }
+
+    #[test]
+    fn found_moves_stay_in_their_game() {
+        let (mut cecp, _) = cecp();
+        cecp.handle("sd 1");
+        cecp.handle("usermove e2e4");
+        cecp.engine.wait();
+        // As when the search ends just before `new` or `undo` arrives
+        cecp.abort();
+        cecp.play_found();
+        assert!(cecp.engine.game.ply() == 1);
+    }
+
+    #[test]
+    fn setboard_and_go() {
+        let (mut cecp, out) = cecp();
+        cecp.handle("force");
+        cecp.handle("sd 2");
+        cecp.handle("setboard 4k3/8/8/7q/8/8/8/4K2R b - - 0 1");
+        cecp.handle("go");
+        cecp.engine.wait();
+        assert!(out.lines().last().unwrap() == "move h5h1");
+
+        // Mating the engine ends the game instead of searching
+        cecp.handle("setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        cecp.handle("usermove a1a8");
+        assert!(out.lines().last().unwrap() == "1-0 {White mates}");
+        assert!(!cecp.engine.is_searching());
+
+        cecp.handle("setboard bad");
+        assert!(out.lines().last().unwrap().starts_with("tellusererror"));
+    }
+
+    #[test]
+    fn time_controls() {
+        let (mut cecp, _) = cecp();
+        cecp.handle("level 40 2:30 1.5");
+        cecp.handle("time 6000");
+        cecp.handle("otim 3000");
+        cecp.handle("sd 4");
+        let limits = cecp.limits();
+        assert!(limits.movestogo == Some(40) && limits.depth == Some(4));
+        assert!(limits.inc == [Duration::from_millis(1500); 2]);
+        // The engine plays black after `new`
+        assert!(limits.time[Side::Black as usize] == Some(Duration::from_secs(60)));
+        assert!(limits.time[Side::White as usize] == Some(Duration::from_secs(30)));
+
+        cecp.handle("st 2");
+        assert!(cecp.limits().movetime == Some(Duration::from_secs(2)));
+        cecp.handle("level 0 5 0");
+        let limits = cecp.limits();
+        assert!(limits.movestogo.is_none() && limits.movetime.is_none());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::api::Piece;
+use crate::bitboard::{parse_fen, BbBoardState};
+use crate::rnd::RndGen;
+
+pub const POSITIONS: u16 = 960;
+
+// Start position number of the standard game
+pub const STANDARD_ID: u16 = 518;
+
+// Where the two knights go among the five squares left after the bishops and queen
+const KNIGHTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+// White's back rank for start position `id`, files a to h, using the usual
+// numbering: light bishop, dark bishop, queen, knights, then rook, king, rook
+// on the three squares left
+pub fn back_rank(id: u16) -> Option<[Piece; 8]> {
+    if id >= POSITIONS {
+        return None;
+    }
+    let mut rank: [Option<Piece>; 8] = [None; 8];
+    let mut n = id as usize;
+    rank[n % 4 * 2 + 1] = Some(Piece::B);
+    n /= 4;
+    rank[n % 4 * 2] = Some(Piece::B);
+    n /= 4;
+    let queen = n % 6;
+    n /= 6;
+    let (n1, n2) = KNIGHTS[n];
+
+    let mut empty: Vec<usize> = (0..8).filter(|&f| rank[f].is_none()).collect();
+    rank[empty[queen]] = Some(Piece::Q);
+    empty.remove(queen);
+    rank[empty[n1]] = Some(Piece::N);
+    rank[empty[n2]] = Some(Piece::N);
+    let rest: Vec<usize> = (0..8).filter(|&f| rank[f].is_none()).collect();
+    for (f, piece) in rest.into_iter().zip([Piece::R, Piece::K, Piece::R]) {
+        rank[f] = Some(piece);
+    }
+    Some(rank.map(|p| p.unwrap()))
+}
+
+// The position with both sides' pieces mirrored and all castling rights
+pub fn start_position(id: u16) -> Option<BbBoardState> {
+    let rank: String = back_rank(id)?
+        .iter()
+        .map(|p| match p {
+            Piece::P => 'p',
+            Piece::N => 'n',
+            Piece::B => 'b',
+            Piece::R => 'r',
+            Piece::Q => 'q',
+            Piece::K => 'k',
+        })
+        .collect();
+    // With only two rooks KQkq always names the right ones
+    let fen = format!(
+        "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+        rank,
+        rank.to_ascii_uppercase()
+    );
+    parse_fen(fen).ok()
+}
+
+// A uniformly chosen start position and its number
+pub fn random_start_position<R: RndGen<u64>>(rnd: &mut R) -> (u16, BbBoardState) {
+    let id = (rnd.next() % POSITIONS as u64) as u16;
+    (id, start_position(id).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::chess960::*;
+    use crate::perft::perft;
+    use crate::rnd::Xoshiro256p;
+
+    #[test]
+    fn known_ids() {
+        let fen = |id| start_position(id).unwrap().to_fen();
+        assert!(fen(STANDARD_ID) == "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert!(fen(0) == "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+        assert!(fen(959) == "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1");
+        assert!(back_rank(POSITIONS).is_none());
+    }
+
+    #[test]
+    fn every_id_is_a_distinct_valid_start() {
+        let mut ranks = Vec::new();
+        for id in 0..POSITIONS {
+            let rank = back_rank(id).unwrap();
+            let files = |piece| (0..8).filter(|&f| rank[f] == piece).collect::<Vec<usize>>();
+            let (bishops, rooks, king) = (files(Piece::B), files(Piece::R), files(Piece::K));
+            assert!(bishops.len() == 2 && bishops[0] % 2 != bishops[1] % 2);
+            assert!(rooks.len() == 2 && rooks[0] < king[0] && king[0] < rooks[1]);
+            assert!(files(Piece::N).len() == 2 && files(Piece::Q).len() == 1);
+            assert!(!ranks.contains(&rank));
+            ranks.push(rank);
+
+            let board = start_position(id).unwrap();
+            assert!(board.validate().is_empty());
+            assert!(board.legal_moves().len() >= 18);
+        }
+    }
+
+    #[test]
+    fn random_positions_come_from_the_generator() {
+        let mut a = Xoshiro256p::initialize(7);
+        let mut b = Xoshiro256p::initialize(7);
+        for _ in 0..10 {
+            let (id, board) = random_start_position(&mut a);
+            assert!(id < POSITIONS);
+            assert!(random_start_position(&mut b) == (id, board.clone()));
+            assert!(board == start_position(id).unwrap());
+        }
+    }
+
+    #[test]
+    fn perft_chess960() {
+        let positions: [(&str, &[u64]); 4] = [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                &[21, 528, 12189],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                &[21, 807, 18002],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                &[20, 479, 10471],
+            ),
+            (
+                "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+                &[22, 593, 13440],
+            ),
+        ];
+        for (fen, expected) in positions {
+            let mut board = parse_fen(fen.to_string()).unwrap();
+            for (i, &nodes) in expected.iter().enumerate() {
+                assert!(
+                    perft(&mut board, i as u8 + 1) == nodes,
+                    "{} depth {}",
+                    fen,
+                    i + 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn king_and_rook_swap_and_stay_put() {
+        // King on g1 castling with the h1 rook only moves the rook
+        let fen = "4k3/8/8/8/8/8/8/6KR w H - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let m = *board
+            .legal_moves()
+            .iter()
+            .find(|m| m.kind() == MoveType::CastleKing)
+            .unwrap();
+        assert!(m.from() == Square::new(6) && m.to() == Square::new(7));
+        let undo = board.make_move(m);
+        assert!(board.to_fen() == "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+        assert!(board.hash() == board.compute_hash());
+        board.unmake_move(m, undo);
+        assert!(board.to_fen() == "4k3/8/8/8/8/8/8/6KR w K - 0 1");
+
+        // King f1 and rook g1 trade places
+        let fen = "4k3/8/8/8/8/8/8/5KR1 w G - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let m = Move::new(Square::new(5), Square::new(6), MoveType::CastleKing);
+        assert!(board.legal_moves().contains(&m));
+        board.make_move(m);
+        assert!(board.to_fen() == "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+    }
+
+    #[test]
+    fn castling_blocked_by_attack_through_the_rook() {
+        // The b1 rook hides c1 from the a1 queen until it moves
+        let fen = "4k3/8/8/8/8/8/8/qR1K4 w B - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(!board
+            .legal_moves()
+            .iter()
+            .any(|m| m.kind() == MoveType::CastleQueen));
+        let fen = "4k3/8/8/8/8/8/8/1R1K4 w B - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board
+            .legal_moves()
+            .iter()
+            .any(|m| m.kind() == MoveType::CastleQueen));
+    }
+
+    #[test]
+    fn x_fen_and_shredder_fen() {
+        // A second rook outside the castling one needs the file letter in X-FEN
+        let fen = "rr2k2r/8/8/8/8/8/8/R3K1RR w GAkb - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.to_fen() == "rr2k2r/8/8/8/8/8/8/R3K1RR w GQkb - 0 1");
+        assert!(board.to_shredder_fen() == "rr2k2r/8/8/8/8/8/8/R3K1RR w GAhb - 0 1");
+        assert!(parse_fen(board.to_fen()).unwrap() == board);
+        assert!(parse_fen(board.to_shredder_fen()).unwrap() == board);
+
+        let start = start_position(STANDARD_ID).unwrap();
+        assert!(start.to_shredder_fen().contains(" HAha "));
+        let shredder = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1";
+        assert!(parse_fen(shredder.to_string()).unwrap() == start);
+    }
+}
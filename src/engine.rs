@@ -0,0 +1,218 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::api::{GameState, Move};
+use crate::game::Game;
+use crate::search::{search, Limits, SearchInfo};
+
+// Where protocol replies go. Shared with the search thread, so whole lines are
+// written under a lock.
+#[derive(Clone)]
+pub struct Output(Arc<Mutex<dyn Write + Send>>);
+
+impl Output {
+    pub fn new(w: impl Write + Send + 'static) -> Self {
+        Output(Arc::new(Mutex::new(w)))
+    }
+
+    pub fn stdout() -> Self {
+        Output::new(std::io::stdout())
+    }
+
+    pub fn line(&self, s: &str) {
+        let mut w = self.0.lock().unwrap();
+        // Nowhere to report a closed pipe to
+        let _ = writeln!(w, "{}", s);
+        let _ = w.flush();
+    }
+}
+
+// Collects what an `Output` writes, for the protocol tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Buffer {
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+}
+
+// The game being played and the search running on it, shared by the protocols
+pub struct Engine {
+    pub game: Game,
+    stop: Arc<AtomicBool>,
+    // Stopped without wanting the move
+    aborted: Arc<AtomicBool>,
+    // Searching on the opponent's time until the ponder move is played
+    pondering: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            game: Game::start(),
+            stop: Arc::new(AtomicBool::new(false)),
+            aborted: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(false)),
+            worker: None,
+        }
+    }
+
+    // Search the current position on another thread. `report` gets each
+    // iteration and `done` the move found once the search ends, unless aborted.
+    // A ponder search holds its move back until `ponderhit` or `stop`.
+    pub fn go<R, D>(&mut self, limits: Limits, mut report: R, done: D)
+    where
+        R: FnMut(&SearchInfo) + Send + 'static,
+        D: FnOnce(Option<Move>) + Send + 'static,
+    {
+        self.stop();
+        self.stop.store(false, Ordering::SeqCst);
+        self.aborted.store(false, Ordering::SeqCst);
+        self.pondering.store(limits.ponder, Ordering::SeqCst);
+        let board = self.game.board().clone();
+        let history = self.game.position_hashes().to_vec();
+        let (stop, aborted, pondering) = (
+            self.stop.clone(),
+            self.aborted.clone(),
+            self.pondering.clone(),
+        );
+        self.worker = Some(thread::spawn(move || {
+            let best = search(&board, &history, &limits, &stop, &pondering, &mut report).best;
+            let holding = || limits.infinite || pondering.load(Ordering::SeqCst);
+            while holding() && !stop.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if !aborted.load(Ordering::SeqCst) {
+                done(best);
+            }
+        }));
+    }
+
+    // End the search, if any, and wait for it to report its move
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.wait();
+    }
+
+    // The opponent played the move pondered on, so the search carries on as
+    // a normal one with the clock running from now
+    pub fn ponderhit(&self) {
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    // End the search without reporting a move
+    pub fn abort(&mut self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.stop();
+    }
+
+    // Wait for the search to end by itself
+    pub fn wait(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.worker.as_ref().is_some_and(|w| !w.is_finished())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::engine::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn search_reports_and_finishes() {
+        let mut engine = Engine::new();
+        let fen = "4k3/8/8/7q/8/8/8/4K2R b - - 0 1";
+        engine.game = Game::new(parse_fen(fen.to_string()).unwrap());
+        let (tx, rx) = mpsc::channel();
+        let info = tx.clone();
+        let limits = Limits {
+            depth: Some(2),
+            ..Limits::default()
+        };
+        engine.go(
+            limits,
+            move |i| info.send(Err(i.clone())).unwrap(),
+            move |m| tx.send(Ok(m)).unwrap(),
+        );
+        engine.wait();
+        let mut messages: Vec<_> = rx.try_iter().collect();
+        // Taking the rook, after reporting each iteration
+        let best = messages.pop().unwrap().unwrap().unwrap();
+        let depths: Vec<u32> = messages.into_iter().map(|i| i.unwrap_err().depth).collect();
+        assert!(depths == vec![1, 2]);
+        assert!(best.to_string() == "h5h1");
+        assert!(!engine.is_searching());
+    }
+
+    #[test]
+    fn infinite_search_waits_for_stop() {
+        let mut engine = Engine::new();
+        let (tx, rx) = mpsc::channel();
+        let limits = Limits {
+            infinite: true,
+            ..Limits::default()
+        };
+        engine.go(limits, |_| (), move |m| tx.send(m).unwrap());
+        thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_recv().is_err() && engine.is_searching());
+        engine.stop();
+        assert!(rx.recv().unwrap().is_some());
+
+        let (tx, rx) = mpsc::channel();
+        let limits = Limits {
+            infinite: true,
+            ..Limits::default()
+        };
+        engine.go(limits, |_| (), move |m| tx.send(m).unwrap());
+        engine.abort();
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn ponder_search_moves_after_ponderhit() {
+        let mut engine = Engine::new();
+        let (tx, rx) = mpsc::channel();
+        let limits = Limits {
+            depth: Some(1),
+            ponder: true,
+            ..Limits::default()
+        };
+        engine.go(limits, |_| (), move |m| tx.send(m).unwrap());
+        thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_recv().is_err() && engine.is_searching());
+        engine.ponderhit();
+        assert!(rx.recv().unwrap().is_some());
+        engine.wait();
+    }
+}
@@ -0,0 +1,340 @@
+use crate::api::{Board, GameState, Move, Side};
+use crate::bitboard::{BbBoardState, Undo};
+use crate::chess960::{start_position, STANDARD_ID};
+
+// Why a game ended in a draw
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawReason {
+    Stalemate,
+    InsufficientMaterial,
+    // Neither side can mate even with too much material, such as with locked pawns
+    DeadPosition,
+    // The same position five times, drawn without a claim
+    FivefoldRepetition,
+    // 75 moves by each side without a capture or pawn move
+    SeventyFiveMoves,
+    // Draws a player has to claim
+    ThreefoldRepetition,
+    FiftyMoves,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameResult {
+    // The winning side
+    Checkmate(Side),
+    Draw(DrawReason),
+}
+
+// A position and the moves that led to it. Moves taken back with `back` or
+// `goto` are kept until a different move is played, so the game can be
+// stepped through in both directions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Game {
+    board: BbBoardState,
+    // Every move played with what it takes to undo it
+    history: Vec<(Move, Undo)>,
+    // Hash of the position before each move, and of the last position
+    hashes: Vec<u64>,
+    // Number of moves of `history` currently on the board
+    ply: usize,
+}
+
+impl Game {
+    pub fn new(board: BbBoardState) -> Self {
+        Game {
+            hashes: vec![board.hash()],
+            board,
+            history: Vec::new(),
+            ply: 0,
+        }
+    }
+
+    pub fn board(&self) -> &BbBoardState {
+        &self.board
+    }
+
+    // The position the game started from
+    pub fn start_board(&self) -> BbBoardState {
+        let mut board = self.board.clone();
+        for &(m, undo) in self.history[..self.ply].iter().rev() {
+            board.unmake_move(m, undo);
+        }
+        board
+    }
+
+    // Moves played up to the current ply
+    pub fn moves(&self) -> Vec<Move> {
+        self.history[..self.ply].iter().map(|&(m, _)| m).collect()
+    }
+
+    // Hashes of the positions before the current one, oldest first
+    pub fn position_hashes(&self) -> &[u64] {
+        &self.hashes[..self.ply]
+    }
+
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    // Plies recorded, including any ahead of the current one
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    // Play a legal move, dropping any moves ahead of the current ply. False if the
+    // move isn't legal here.
+    pub fn play(&mut self, m: Move) -> bool {
+        if !self.board.test_move(m) {
+            return false;
+        }
+        self.make_move(m);
+        true
+    }
+
+    // Take the last move back for good
+    pub fn takeback(&mut self) -> Option<Move> {
+        let (m, undo) = *self.history[..self.ply].last()?;
+        self.unmake_move(m, undo);
+        Some(m)
+    }
+
+    // Step back one ply, keeping the move to step forward again
+    pub fn back(&mut self) -> bool {
+        match self.ply {
+            0 => false,
+            _ => {
+                self.ply -= 1;
+                let (m, undo) = self.history[self.ply];
+                self.board.unmake_move(m, undo);
+                true
+            }
+        }
+    }
+
+    pub fn forward(&mut self) -> bool {
+        match self.history.get(self.ply) {
+            Some(&(m, _)) => {
+                self.board.make_move(m);
+                self.ply += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Move to any recorded ply by stepping from the current one
+    pub fn goto(&mut self, ply: usize) -> bool {
+        if ply > self.history.len() {
+            return false;
+        }
+        while self.ply > ply {
+            self.back();
+        }
+        while self.ply < ply {
+            self.forward();
+        }
+        true
+    }
+
+    // Times the current position has occurred. Only positions since the last
+    // capture or pawn move, with the same side to move, can match.
+    pub fn repetitions(&self) -> usize {
+        let current = self.hashes[self.ply];
+        let window = (self.board.halfmove_clock() as usize).min(self.ply);
+        (0..=window)
+            .step_by(2)
+            .filter(|&back| self.hashes[self.ply - back] == current)
+            .count()
+    }
+
+    // How the game ended, if it has. Draws that need a claim aren't included.
+    pub fn result(&self) -> Option<GameResult> {
+        if self.board.legal_moves().is_empty() {
+            return Some(match self.board.in_check() {
+                true => GameResult::Checkmate(self.board.to_move.opposite()),
+                false => GameResult::Draw(DrawReason::Stalemate),
+            });
+        }
+        let pieces = &self.board.pieces;
+        let reason = if pieces.is_insufficient_material() {
+            DrawReason::InsufficientMaterial
+        } else if pieces.is_dead_position() {
+            DrawReason::DeadPosition
+        } else if self.repetitions() >= 5 {
+            DrawReason::FivefoldRepetition
+        } else if self.board.is_seventy_five_move_draw() {
+            DrawReason::SeventyFiveMoves
+        } else {
+            return None;
+        };
+        Some(GameResult::Draw(reason))
+    }
+
+    // A draw the side to move could claim now
+    pub fn claimable_draw(&self) -> Option<DrawReason> {
+        if self.repetitions() >= 3 {
+            Some(DrawReason::ThreefoldRepetition)
+        } else if self.board.is_fifty_move_draw() {
+            Some(DrawReason::FiftyMoves)
+        } else {
+            None
+        }
+    }
+}
+
+impl GameState for Game {
+    type Undo = Undo;
+
+    fn start() -> Self {
+        Game::new(start_position(STANDARD_ID).unwrap())
+    }
+
+    fn empty() -> Self {
+        Game::new(BbBoardState::empty())
+    }
+
+    fn pretty_print(&self) -> String {
+        self.board.pretty_print()
+    }
+
+    fn is_legal(&self) -> bool {
+        self.board.is_legal()
+    }
+
+    // Moves ahead of the current ply are dropped
+    fn make_move(&mut self, m: Move) -> Undo {
+        let undo = self.board.make_move(m);
+        self.history.truncate(self.ply);
+        self.hashes.truncate(self.ply + 1);
+        self.history.push((m, undo));
+        self.hashes.push(self.board.hash());
+        self.ply += 1;
+        undo
+    }
+
+    fn unmake_move(&mut self, m: Move, undo: Undo) {
+        self.board.unmake_move(m, undo);
+        self.ply = self.ply.saturating_sub(1);
+        self.history.truncate(self.ply);
+        self.hashes.truncate(self.ply + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+    use crate::chess960::*;
+    use crate::game::*;
+
+    fn game(fen: &str) -> Game {
+        Game::new(parse_fen(fen.to_string()).unwrap())
+    }
+
+    fn play(game: &mut Game, moves: &[&str]) {
+        for san in moves {
+            let m = game.board().parse_san(san).unwrap();
+            assert!(game.play(m), "{}", san);
+        }
+    }
+
+    #[test]
+    fn checkmate_and_stalemate() {
+        let mut g = Game::start();
+        play(&mut g, &["f3", "e5", "g4"]);
+        assert!(g.result().is_none());
+        play(&mut g, &["Qh4"]);
+        assert!(g.result() == Some(GameResult::Checkmate(Side::Black)));
+
+        let g = game("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert!(g.result() == Some(GameResult::Draw(DrawReason::Stalemate)));
+    }
+
+    #[test]
+    fn repetitions() {
+        let mut g = Game::start();
+        let shuffle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+        play(&mut g, &shuffle);
+        assert!(g.repetitions() == 2 && g.claimable_draw().is_none());
+        play(&mut g, &shuffle);
+        assert!(g.repetitions() == 3);
+        assert!(g.claimable_draw() == Some(DrawReason::ThreefoldRepetition));
+        assert!(g.result().is_none());
+        play(&mut g, &shuffle);
+        play(&mut g, &shuffle);
+        assert!(g.result() == Some(GameResult::Draw(DrawReason::FivefoldRepetition)));
+        // A pawn move resets the count
+        play(&mut g, &["e4"]);
+        assert!(g.repetitions() == 1 && g.result().is_none());
+
+        // Double pushes that can't be taken en passant don't make positions differ
+        let mut g = Game::start();
+        play(&mut g, &["e4", "e5"]);
+        play(&mut g, &shuffle);
+        play(&mut g, &shuffle);
+        assert!(g.repetitions() == 3);
+        assert!(g.claimable_draw() == Some(DrawReason::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn move_rules() {
+        let g = game("4k3/8/8/8/8/8/8/R3K3 w - - 100 80");
+        assert!(g.claimable_draw() == Some(DrawReason::FiftyMoves));
+        assert!(g.result().is_none());
+        let g = game("4k3/8/8/8/8/8/8/R3K3 w - - 150 80");
+        assert!(g.result() == Some(GameResult::Draw(DrawReason::SeventyFiveMoves)));
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let drawn = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+        ];
+        for fen in drawn {
+            assert!(game(fen).result() == Some(GameResult::Draw(DrawReason::InsufficientMaterial)));
+        }
+        let playable = [
+            "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+        ];
+        for fen in playable {
+            assert!(game(fen).result().is_none());
+        }
+        let locked = game("8/8/1k6/p1p1p1p1/P1P1P1P1/8/8/4K3 w - - 0 1");
+        assert!(locked.result() == Some(GameResult::Draw(DrawReason::DeadPosition)));
+    }
+
+    #[test]
+    fn takeback_and_navigation() {
+        let mut g = Game::start();
+        play(&mut g, &["e4", "e5", "Nf3", "Nc6"]);
+        let end = g.board().clone();
+        assert!(g.goto(1));
+        assert!(
+            g.board().to_fen() == "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+        assert!(g.moves().len() == 1 && g.len() == 4);
+        assert!(g.goto(4) && *g.board() == end);
+        assert!(!g.goto(5));
+        assert!(g.goto(0) && *g.board() == start_position(STANDARD_ID).unwrap());
+        assert!(!g.back());
+        assert!(g.start_board() == start_position(STANDARD_ID).unwrap());
+
+        // Playing from an earlier ply drops the moves after it
+        g.goto(2);
+        play(&mut g, &["Bc4"]);
+        assert!(g.len() == 3 && !g.forward());
+
+        let bc4 = g.takeback().unwrap();
+        assert!(bc4.to_string() == "f1c4");
+        assert!(g.ply() == 2 && g.len() == 2);
+        let illegal = Move::new(Square::new(4), Square::new(20), MoveType::Quiet);
+        assert!(!g.play(illegal));
+    }
+}
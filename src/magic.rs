@@ -0,0 +1,215 @@
+use std::sync::OnceLock;
+
+use crate::api::Square;
+use crate::attacks::{bishop_rays, rook_rays};
+use crate::bitboard::Bitboard;
+use crate::rnd::{RndGen, Xoshiro256p};
+
+// Fixed seed so the magic numbers, and the tables built from them, are reproducible
+pub const MAGIC_SEED: u64 = 0x6d61_6769_6373;
+
+const RANK_1: u64 = 0xff;
+const RANK_8: u64 = 0xff00000000000000;
+const FILE_A: u64 = 0x0101010101010101;
+const FILE_H: u64 = 0x8080808080808080;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Magic {
+    pub mask: u64,
+    pub magic: u64,
+    pub shift: u8,
+    offset: usize,
+}
+
+impl Magic {
+    #[inline]
+    fn index(&self, occupied: Bitboard) -> usize {
+        ((occupied.v & self.mask).wrapping_mul(self.magic) >> self.shift) as usize + self.offset
+    }
+}
+
+struct SliderTable {
+    magics: [Magic; 64],
+    attacks: Vec<Bitboard>,
+}
+
+struct Magics {
+    rook: SliderTable,
+    bishop: SliderTable,
+}
+
+static MAGICS: OnceLock<Magics> = OnceLock::new();
+
+fn magics() -> &'static Magics {
+    MAGICS.get_or_init(|| {
+        let mut rnd = Xoshiro256p::initialize(MAGIC_SEED);
+        Magics {
+            rook: build_table(&mut rnd, rook_mask, rook_rays),
+            bishop: build_table(&mut rnd, bishop_mask, bishop_rays),
+        }
+    })
+}
+
+// Search for the magic numbers now instead of on the first lookup
+pub fn init() {
+    magics();
+}
+
+#[inline]
+pub fn rook_attacks(s: Square, occupied: Bitboard) -> Bitboard {
+    let table = &magics().rook;
+    table.attacks[table.magics[s.v as usize].index(occupied)]
+}
+
+#[inline]
+pub fn bishop_attacks(s: Square, occupied: Bitboard) -> Bitboard {
+    let table = &magics().bishop;
+    table.attacks[table.magics[s.v as usize].index(occupied)]
+}
+
+pub fn rook_magic(s: Square) -> Magic {
+    magics().rook.magics[s.v as usize]
+}
+
+pub fn bishop_magic(s: Square) -> Magic {
+    magics().bishop.magics[s.v as usize]
+}
+
+// Squares whose occupancy affects a rook on `s`. The last square of each ray never
+// blocks anything, so it is left out.
+fn rook_mask(s: Square) -> u64 {
+    let rank = RANK_1 << (s.rank_index() * 8);
+    let file = FILE_A << s.file_index();
+    let edges = ((RANK_1 | RANK_8) & !rank) | ((FILE_A | FILE_H) & !file);
+    rook_rays(s, Bitboard::empty()).v & !edges
+}
+
+fn bishop_mask(s: Square) -> u64 {
+    bishop_rays(s, Bitboard::empty()).v & !(RANK_1 | RANK_8 | FILE_A | FILE_H)
+}
+
+fn build_table(
+    rnd: &mut Xoshiro256p,
+    mask: fn(Square) -> u64,
+    rays: fn(Square, Bitboard) -> Bitboard,
+) -> SliderTable {
+    let mut magics = [Magic {
+        mask: 0,
+        magic: 0,
+        shift: 0,
+        offset: 0,
+    }; 64];
+    let mut attacks = Vec::new();
+    for (i, m) in magics.iter_mut().enumerate() {
+        let s = Square::new(i as u8);
+        let (magic, table) = find_magic(rnd, s, mask(s), rays);
+        *m = Magic {
+            offset: attacks.len(),
+            ..magic
+        };
+        attacks.extend(table);
+    }
+    SliderTable { magics, attacks }
+}
+
+// Try sparse random numbers until one maps every occupancy of `mask` to a slot
+// without two different attack sets colliding
+fn find_magic(
+    rnd: &mut Xoshiro256p,
+    s: Square,
+    mask: u64,
+    rays: fn(Square, Bitboard) -> Bitboard,
+) -> (Magic, Vec<Bitboard>) {
+    let bits = mask.count_ones();
+    let shift = (64 - bits) as u8;
+
+    // Enumerate all subsets of the mask (Carry-Rippler)
+    let mut occupancies = Vec::with_capacity(1 << bits);
+    let mut subset: u64 = 0;
+    loop {
+        occupancies.push((subset, rays(s, Bitboard::new(subset))));
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            break;
+        }
+    }
+
+    let mut table = vec![Bitboard::empty(); 1 << bits];
+    // Slot `i` is filled for the current attempt when `used[i] == attempt`
+    let mut used = vec![0u32; 1 << bits];
+    let mut attempt = 0;
+    loop {
+        let magic = rnd.next() & rnd.next() & rnd.next();
+        if (mask.wrapping_mul(magic) & 0xff00000000000000).count_ones() < 6 {
+            continue;
+        }
+        attempt += 1;
+        let fits = occupancies.iter().all(|&(occ, att)| {
+            let i = (occ.wrapping_mul(magic) >> shift) as usize;
+            if used[i] != attempt {
+                used[i] = attempt;
+                table[i] = att;
+                true
+            } else {
+                table[i] == att
+            }
+        });
+        if fits {
+            let magic = Magic {
+                mask,
+                magic,
+                shift,
+                offset: 0,
+            };
+            return (magic, table);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::magic::*;
+
+    fn check_all_squares(
+        lookup: fn(Square, Bitboard) -> Bitboard,
+        rays: fn(Square, Bitboard) -> Bitboard,
+    ) {
+        let mut rnd = Xoshiro256p::initialize(12345);
+        for i in 0..64 {
+            let s = Square::new(i);
+            for _ in 0..200 {
+                let occ = Bitboard::new(rnd.next() & rnd.next());
+                assert!(lookup(s, occ) == rays(s, occ), "square {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn rook_lookup_matches_rays() {
+        check_all_squares(rook_attacks, rook_rays);
+    }
+
+    #[test]
+    fn bishop_lookup_matches_rays() {
+        check_all_squares(bishop_attacks, bishop_rays);
+    }
+
+    #[test]
+    fn mask_sizes() {
+        // a1, d4
+        assert!(rook_mask(Square::new(0)).count_ones() == 12);
+        assert!(rook_mask(Square::new(27)).count_ones() == 10);
+        assert!(bishop_mask(Square::new(0)).count_ones() == 6);
+        assert!(bishop_mask(Square::new(27)).count_ones() == 9);
+    }
+
+    #[test]
+    fn search_is_reproducible_from_seed() {
+        let s = Square::new(9);
+        let mut a = Xoshiro256p::initialize(MAGIC_SEED);
+        let mut b = Xoshiro256p::initialize(MAGIC_SEED);
+        let (ma, _) = find_magic(&mut a, s, rook_mask(s), rook_rays);
+        let (mb, _) = find_magic(&mut b, s, rook_mask(s), rook_rays);
+        assert!(ma == mb);
+    }
+}
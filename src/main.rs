@@ -1,12 +1,39 @@
-#![feature(result_contains_err)]
-#![feature(is_some_and)]
 #![allow(dead_code)]
 
+pub mod attacks;
 pub mod bitboard;
+pub mod castling;
+pub mod cecp;
+pub mod chess960;
+pub mod engine;
+pub mod game;
 pub mod utils;
 pub mod api;
+pub mod magic;
+pub mod material;
+pub mod movegen;
+pub mod perft;
+pub mod pgn;
+pub mod r#move;
 pub mod rnd;
+pub mod san;
+pub mod search;
+pub mod tables;
+pub mod uci;
+pub mod validate;
+pub mod zobrist;
+
+use std::io::BufRead;
 
 fn main() {
-    println!("Hello, world!");
+    magic::init();
+    let out = engine::Output::stdout();
+    let mut lines = std::io::stdin().lock().lines().map_while(Result::ok);
+    // XBoard opens with `xboard`, a UCI GUI with `uci`
+    let first = lines.next().unwrap_or_default();
+    let lines = std::iter::once(first.clone()).chain(lines);
+    match first.trim() {
+        "xboard" => cecp::run(lines, out),
+        _ => uci::run(lines, out),
+    }
 }
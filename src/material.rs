@@ -0,0 +1,141 @@
+use crate::api::{Direction, Piece, Side};
+use crate::bitboard::{BbPieceState, Bitboard};
+
+const LIGHT_SQUARES: Bitboard = Bitboard::new(0x55aa55aa55aa55aa);
+
+// Squares a king on `from` can walk to without stepping on `blocked`
+fn reachable(from: Bitboard, blocked: Bitboard) -> Bitboard {
+    let mut region = from;
+    loop {
+        let step = Direction::COMPASS
+            .iter()
+            .fold(region, |bb, &d| bb | region.shift(d));
+        let next = region | (step & !blocked);
+        if next == region {
+            return region;
+        }
+        region = next;
+    }
+}
+
+impl BbPieceState {
+    fn both(&self, piece: Piece) -> Bitboard {
+        self.get(Side::White, piece) | self.get(Side::Black, piece)
+    }
+
+    // Too little left for either side to mate: kings with a single knight, or
+    // with any number of bishops all on squares of one colour
+    pub fn is_insufficient_material(&self) -> bool {
+        if !(self.both(Piece::P) | self.both(Piece::R) | self.both(Piece::Q)).is_empty() {
+            return false;
+        }
+        let bishops = self.both(Piece::B);
+        match self.both(Piece::N).count_bits() {
+            0 => bishops.is_subset(LIGHT_SQUARES) || bishops.is_disjoint(LIGHT_SQUARES),
+            1 => bishops.is_empty(),
+            _ => false,
+        }
+    }
+
+    // No sequence of legal moves can end in mate, whoever is to move. Besides
+    // insufficient material this finds kings and pawns only, with every pawn
+    // blocked for good and neither king able to reach an enemy pawn.
+    pub fn is_dead_position(&self) -> bool {
+        self.is_insufficient_material() || self.is_locked_pawn_position()
+    }
+
+    fn is_locked_pawn_position(&self) -> bool {
+        let pieces = self.both(Piece::N) | self.both(Piece::B) | self.both(Piece::R);
+        if !(pieces | self.both(Piece::Q)).is_empty() {
+            return false;
+        }
+        let white = self.get(Side::White, Piece::P);
+        let black = self.get(Side::Black, Piece::P);
+        let pawns = white | black;
+        // Each pawn has a pawn right in front of it and nothing to take
+        if !white.shift(Direction::N).is_subset(pawns)
+            || !black.shift(Direction::S).is_subset(pawns)
+        {
+            return false;
+        }
+        let white_attacks = white.shift(Direction::NE) | white.shift(Direction::NW);
+        let black_attacks = black.shift(Direction::SE) | black.shift(Direction::SW);
+        if !white_attacks.is_disjoint(black) || !black_attacks.is_disjoint(white) {
+            return false;
+        }
+
+        for (side, own, theirs, attacked) in [
+            (Side::White, white, black, black_attacks),
+            (Side::Black, black, white, white_attacks),
+        ] {
+            let king = self.get(side, Piece::K);
+            if king.count_bits() != 1 || !king.is_disjoint(attacked) {
+                return false;
+            }
+            if !reachable(king, own | attacked).is_disjoint(theirs) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+
+    fn pieces(fen: &str) -> BbPieceState {
+        parse_fen(fen.to_string()).unwrap().pieces
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let dead = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 b - - 0 1",
+            // Bishops on dark squares only
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/B1B1K3 w - - 0 1",
+        ];
+        for fen in dead {
+            assert!(pieces(fen).is_insufficient_material(), "{}", fen);
+            assert!(pieces(fen).is_dead_position(), "{}", fen);
+        }
+        let alive = [
+            "4k1b1/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/NN2K3 w - - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+        ];
+        for fen in alive {
+            assert!(!pieces(fen).is_insufficient_material(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn locked_pawn_chains() {
+        let dead = [
+            "8/8/1k6/p1p1p1p1/P1P1P1P1/8/8/4K3 w - - 0 1",
+            "8/4k3/8/2p1p1p1/p1P1P1P1/P7/8/4K3 b - - 0 1",
+        ];
+        for fen in dead {
+            assert!(!pieces(fen).is_insufficient_material(), "{}", fen);
+            assert!(pieces(fen).is_dead_position(), "{}", fen);
+        }
+        let alive = [
+            // The white king gets round the g file to the e5 pawn
+            "8/8/1k6/p1p1p3/P1P1P3/8/8/4K3 w - - 0 1",
+            // Blocked, but a4 and b4 can take
+            "8/8/2k5/pp6/PP6/8/8/4K3 w - - 0 1",
+            // e4 can still advance
+            "8/8/1k6/p1p3p1/P1P1P1P1/8/8/4K3 w - - 0 1",
+            "8/8/1k6/p1p1p1p1/P1P1P1P1/8/8/4K2R w - - 0 1",
+        ];
+        for fen in alive {
+            assert!(!pieces(fen).is_dead_position(), "{}", fen);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::api::{Move, MoveType, NotationError, PromotionType, Square};
+use crate::bitboard::BbBoardState;
+
+// A move packed into 16 bits: from-square in bits 0-5, to-square in bits 6-11 and
+// the move kind in bits 12-15. Small enough for move lists and transposition entries.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedMove(u16);
+
+const QUIET: u16 = 0;
+const DOUBLE_PUSH: u16 = 1;
+const CASTLE_KING: u16 = 2;
+const CASTLE_QUEEN: u16 = 3;
+const CAPTURE: u16 = 4;
+const EN_PASSANT: u16 = 5;
+// Promotions set bit 3 of the flags, promotion captures bits 2 and 3. The piece
+// is in the low two bits.
+const PROMOTE: u16 = 8;
+const PROMOTE_CAPTURE: u16 = 12;
+
+const fn promotion_bits(p: PromotionType) -> u16 {
+    match p {
+        PromotionType::Knight => 0,
+        PromotionType::Bishop => 1,
+        PromotionType::Rook => 2,
+        PromotionType::Queen => 3,
+    }
+}
+
+const fn promotion_from_bits(b: u16) -> PromotionType {
+    match b & 3 {
+        0 => PromotionType::Knight,
+        1 => PromotionType::Bishop,
+        2 => PromotionType::Rook,
+        _ => PromotionType::Queen,
+    }
+}
+
+// The four flag bits for a move kind
+pub const fn kind_to_flags(kind: MoveType) -> u16 {
+    match kind {
+        MoveType::Quiet => QUIET,
+        MoveType::DoublePush => DOUBLE_PUSH,
+        MoveType::CastleKing => CASTLE_KING,
+        MoveType::CastleQueen => CASTLE_QUEEN,
+        MoveType::Capture => CAPTURE,
+        MoveType::EnPassant => EN_PASSANT,
+        MoveType::Promote(p) => PROMOTE | promotion_bits(p),
+        MoveType::PromoteCapture(p) => PROMOTE_CAPTURE | promotion_bits(p),
+    }
+}
+
+// The move kind for four flag bits. Flags 6 and 7 are unused.
+pub const fn flags_to_kind(flags: u16) -> Option<MoveType> {
+    match flags {
+        QUIET => Some(MoveType::Quiet),
+        DOUBLE_PUSH => Some(MoveType::DoublePush),
+        CASTLE_KING => Some(MoveType::CastleKing),
+        CASTLE_QUEEN => Some(MoveType::CastleQueen),
+        CAPTURE => Some(MoveType::Capture),
+        EN_PASSANT => Some(MoveType::EnPassant),
+        8..=11 => Some(MoveType::Promote(promotion_from_bits(flags))),
+        12..=15 => Some(MoveType::PromoteCapture(promotion_from_bits(flags))),
+        _ => None,
+    }
+}
+
+impl PackedMove {
+    // All zeros, a1 to a1 quiet, which is never a real move
+    pub const NULL: PackedMove = PackedMove(0);
+
+    pub const fn new(from: Square, to: Square, kind: MoveType) -> Self {
+        PackedMove(from.v as u16 | (to.v as u16) << 6 | kind_to_flags(kind) << 12)
+    }
+
+    // Accepts any 16 bits except those with unused flags
+    pub const fn from_bits(bits: u16) -> Option<Self> {
+        match flags_to_kind(bits >> 12) {
+            Some(_) => Some(PackedMove(bits)),
+            None => None,
+        }
+    }
+
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub const fn from(&self) -> Square {
+        Square::new((self.0 & 0x3f) as u8)
+    }
+
+    pub const fn to(&self) -> Square {
+        Square::new((self.0 >> 6 & 0x3f) as u8)
+    }
+
+    pub const fn flags(&self) -> u16 {
+        self.0 >> 12
+    }
+
+    pub const fn kind(&self) -> MoveType {
+        match flags_to_kind(self.flags()) {
+            Some(kind) => kind,
+            None => MoveType::Quiet,
+        }
+    }
+
+    pub const fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn is_capture(&self) -> bool {
+        self.flags() & CAPTURE != 0
+    }
+
+    pub const fn is_promotion(&self) -> bool {
+        self.flags() & PROMOTE != 0
+    }
+
+    pub const fn unpack(&self) -> Move {
+        Move::new(self.from(), self.to(), self.kind())
+    }
+}
+
+impl From<Move> for PackedMove {
+    fn from(m: Move) -> Self {
+        PackedMove::new(m.from(), m.to(), m.kind())
+    }
+}
+
+impl From<PackedMove> for Move {
+    fn from(m: PackedMove) -> Self {
+        m.unpack()
+    }
+}
+
+impl BbBoardState {
+    // The legal move written in coordinate notation. With `king_takes_rook` castling
+    // is read as the king moving onto its rook, otherwise as the king's own move;
+    // in Chess960 the two can clash with ordinary king moves.
+    pub fn parse_uci(&self, s: &str, king_takes_rook: bool) -> Result<Move, NotationError> {
+        let uci = s.parse::<Move>()?.to_uci(false);
+        self.legal_moves()
+            .into_iter()
+            .find(|m| m.to_uci(king_takes_rook) == uci)
+            .ok_or_else(|| NotationError::illegal(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+    use crate::r#move::*;
+
+    #[test]
+    fn packed_move_is_two_bytes() {
+        assert!(std::mem::size_of::<PackedMove>() == 2);
+    }
+
+    #[test]
+    fn bit_layout() {
+        // e2e4
+        let m = PackedMove::new(Square::new(12), Square::new(28), MoveType::DoublePush);
+        assert!(m.bits() == 12 | 28 << 6 | 1 << 12);
+        // h7xg8=Q
+        let kind = MoveType::PromoteCapture(PromotionType::Queen);
+        let m = PackedMove::new(Square::new(55), Square::new(62), kind);
+        assert!(m.bits() == 55 | 62 << 6 | 15 << 12);
+        assert!(m.is_capture() && m.is_promotion());
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        for flags in 0..16 {
+            match flags_to_kind(flags) {
+                Some(kind) => assert!(kind_to_flags(kind) == flags),
+                None => assert!(flags == 6 || flags == 7),
+            }
+        }
+        assert!(PackedMove::from_bits(6 << 12).is_none());
+        assert!(PackedMove::from_bits(0xffff).is_some());
+    }
+
+    #[test]
+    fn capture_and_promotion_flags() {
+        let quiet = PackedMove::new(Square::new(1), Square::new(18), MoveType::Quiet);
+        let ep = PackedMove::new(Square::new(36), Square::new(43), MoveType::EnPassant);
+        let castle = PackedMove::new(Square::new(4), Square::new(7), MoveType::CastleKing);
+        let promote = MoveType::Promote(PromotionType::Knight);
+        let promote = PackedMove::new(Square::new(48), Square::new(56), promote);
+        assert!(!quiet.is_capture() && !quiet.is_promotion());
+        assert!(ep.is_capture() && !ep.is_promotion());
+        assert!(!castle.is_capture() && !castle.is_promotion());
+        assert!(!promote.is_capture() && promote.is_promotion());
+        assert!(PackedMove::NULL.is_null() && !quiet.is_null());
+    }
+
+    #[test]
+    fn square_notation() {
+        assert!(Square::new(28).to_string() == "e4");
+        assert!("e4".parse::<Square>() == Ok(Square::new(28)));
+        assert!("a1".parse::<Square>() == Ok(Square::new(0)));
+        assert!("h8".parse::<Square>() == Ok(Square::new(63)));
+        for s in ["", "e", "e9", "i1", "E4", "e44"] {
+            let e = s.parse::<Square>().unwrap_err();
+            assert!(e.kind == NotationErrorKind::Syntax);
+        }
+        for v in 0..64 {
+            assert!(Square::new(v).to_string().parse::<Square>() == Ok(Square::new(v)));
+        }
+    }
+
+    #[test]
+    fn move_notation() {
+        let m: Move = "e7e8q".parse().unwrap();
+        assert!(m.from() == Square::new(52) && m.to() == Square::new(60));
+        assert!(m.kind() == MoveType::Promote(PromotionType::Queen));
+        assert!(m.to_string() == "e7e8q");
+        assert!("N".parse::<PromotionType>() == Ok(PromotionType::Knight));
+        assert!(PromotionType::Rook.to_string() == "r");
+        for s in ["e2", "e2e", "e2e4x", "e7e8k", "e2-e4", "é2e4"] {
+            assert!(s.parse::<Move>().is_err(), "{}", s);
+        }
+
+        let castle = Move::new(Square::new(4), Square::new(7), MoveType::CastleKing);
+        assert!(castle.to_string() == "e1g1");
+        assert!(castle.to_uci(true) == "e1h1");
+    }
+
+    #[test]
+    fn parse_uci_against_position() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        let m = board.parse_uci("e1g1", false).unwrap();
+        assert!(m.kind() == MoveType::CastleKing);
+        assert!(board.parse_uci("e1h1", true) == Ok(m));
+        assert!(board.parse_uci("e1h1", false).unwrap_err().kind == NotationErrorKind::Illegal);
+        assert!(board.parse_uci("d5e6", false).unwrap().kind() == MoveType::Capture);
+        assert!(board.parse_uci("a2a4", false).unwrap().kind() == MoveType::DoublePush);
+        let e = board.parse_uci("e2e5", false).unwrap_err();
+        assert!(e.to_string() == "'e2e5' is not a legal move");
+        assert!(board.parse_uci("e2", false).unwrap_err().kind == NotationErrorKind::Syntax);
+
+        // A king on b1 next to its a1 rook: b1c1 is a plain king move and b1a1,
+        // the king taking its rook, is castling
+        let fen = "4k3/8/8/8/8/8/8/RK6 w A - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.parse_uci("b1c1", true).unwrap().kind() == MoveType::Quiet);
+        assert!(board.parse_uci("b1a1", true).unwrap().kind() == MoveType::CastleQueen);
+
+        let fen = "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        for m in board.legal_moves() {
+            assert!(board.parse_uci(&m.to_string(), false) == Ok(m));
+            assert!(board.parse_uci(&m.to_uci(true), true) == Ok(m));
+        }
+    }
+
+    #[test]
+    fn legal_moves_round_trip() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        ];
+        for fen in fens {
+            for m in parse_fen(fen.to_string()).unwrap().legal_moves() {
+                let packed: PackedMove = m.into();
+                let unpacked: Move = packed.into();
+                assert!(unpacked == m);
+                assert!(PackedMove::from_bits(packed.bits()) == Some(packed));
+            }
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use crate::api::{Direction, Move, MoveType, Piece, PromotionType, Rank, Side, Square};
+use crate::attacks::*;
+use crate::bitboard::{BbBoardState, Bitboard};
+use crate::castling::WINGS;
+
+const PROMOTIONS: [PromotionType; 4] = [
+    PromotionType::Queen,
+    PromotionType::Rook,
+    PromotionType::Bishop,
+    PromotionType::Knight,
+];
+
+impl BbBoardState {
+    // Every move for the side to move that obeys piece movement rules. Moves
+    // which leave the own king in check are included.
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(64);
+        let us = self.to_move;
+        let own = self.pieces.side(us);
+        let enemy = self.pieces.side(us.opposite());
+        let occupied = own | enemy;
+        let targets = !own;
+
+        self.pawn_moves(&mut moves, enemy, occupied);
+
+        for from in self.pieces.get(us, Piece::N) {
+            push_moves(&mut moves, from, knight_attacks(from) & targets, enemy);
+        }
+        for from in self.pieces.get(us, Piece::B) {
+            push_moves(
+                &mut moves,
+                from,
+                bishop_attacks(from, occupied) & targets,
+                enemy,
+            );
+        }
+        for from in self.pieces.get(us, Piece::R) {
+            push_moves(
+                &mut moves,
+                from,
+                rook_attacks(from, occupied) & targets,
+                enemy,
+            );
+        }
+        for from in self.pieces.get(us, Piece::Q) {
+            push_moves(
+                &mut moves,
+                from,
+                queen_attacks(from, occupied) & targets,
+                enemy,
+            );
+        }
+        for from in self.pieces.get(us, Piece::K) {
+            push_moves(&mut moves, from, king_attacks(from) & targets, enemy);
+        }
+
+        self.castling_moves(&mut moves, occupied);
+        moves
+    }
+
+    // Every strictly legal move for the side to move
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let us = self.to_move;
+        let them = us.opposite();
+        let moves = self.pseudo_legal_moves();
+        let kings = self.pieces.get(us, Piece::K);
+        if kings.is_empty() {
+            return moves;
+        }
+        let king = kings.lsb();
+        let occupied = self.pieces.occupied();
+
+        let checkers = self.checkers();
+        // Squares a non-king move has to land on to resolve a check
+        let evasions = match checkers.count_bits() {
+            0 => Bitboard::full(),
+            1 => checkers | between(king, checkers.lsb()),
+            _ => Bitboard::empty(),
+        };
+        let (pinned, _) = self.pinned(us);
+        // The king must not stay on a slider's ray by stepping away from it
+        let without_king = occupied ^ kings;
+
+        moves
+            .into_iter()
+            .filter(|m| {
+                let from = Bitboard::get_coord(m.from());
+                let to = Bitboard::get_coord(m.to());
+                match m.kind() {
+                    MoveType::CastleKing | MoveType::CastleQueen => true,
+                    MoveType::EnPassant => self.en_passant_is_legal(*m, king),
+                    _ if m.from() == king => {
+                        !is_attacked_by(&self.pieces, m.to(), them, without_king)
+                    }
+                    _ => {
+                        !evasions.is_disjoint(to)
+                            && (pinned.is_disjoint(from) || !line(king, m.from()).is_disjoint(to))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // En passant removes two pieces from a rank at once, so test the resulting
+    // occupancy directly rather than relying on pins
+    fn en_passant_is_legal(&self, m: Move, king: Square) -> bool {
+        let us = self.to_move;
+        let them = us.opposite();
+        let captured = match us {
+            Side::White => Square::new(m.to().v - 8),
+            Side::Black => Square::new(m.to().v + 8),
+        };
+        let mut pieces = self.pieces.clone();
+        *pieces.get_mut(them, Piece::P) ^= Bitboard::get_coord(captured);
+        let occupied = (self.pieces.occupied()
+            ^ Bitboard::get_coord(m.from())
+            ^ Bitboard::get_coord(captured))
+            | Bitboard::get_coord(m.to());
+        !is_attacked_by(&pieces, king, them, occupied)
+    }
+
+    pub fn in_check(&self) -> bool {
+        let kings = self.pieces.get(self.to_move, Piece::K);
+        !kings.is_empty()
+            && is_attacked_by(
+                &self.pieces,
+                kings.lsb(),
+                self.to_move.opposite(),
+                self.pieces.occupied(),
+            )
+    }
+
+    // Target square of an en passant capture, if one is available
+    pub fn en_passant_square(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    // Can the side to move actually capture en passant. Cheap enough for
+    // make_move: only the pawns next to the pushed one are looked at.
+    pub fn has_legal_en_passant(&self) -> bool {
+        let ep = match self.en_passant {
+            Some(ep) => ep,
+            None => return false,
+        };
+        let us = self.to_move;
+        let pawns = pawn_attacks(ep, us.opposite()) & self.pieces.get(us, Piece::P);
+        let kings = self.pieces.get(us, Piece::K);
+        pawns.into_iter().any(|from| {
+            kings.is_empty()
+                || self.en_passant_is_legal(Move::new(from, ep, MoveType::EnPassant), kings.lsb())
+        })
+    }
+
+    fn pawn_moves(&self, moves: &mut Vec<Move>, enemy: Bitboard, occupied: Bitboard) {
+        let us = self.to_move;
+        let (forward, start_rank, last_rank) = match us {
+            Side::White => (
+                Direction::N,
+                Bitboard::rank(Rank::R2),
+                Bitboard::rank(Rank::R8),
+            ),
+            Side::Black => (
+                Direction::S,
+                Bitboard::rank(Rank::R7),
+                Bitboard::rank(Rank::R1),
+            ),
+        };
+        let ep = self.en_passant_square();
+
+        for from in self.pieces.get(us, Piece::P) {
+            let coord = Bitboard::get_coord(from);
+
+            let one = coord.shift(forward);
+            if !one.is_empty() && occupied.is_disjoint(one) {
+                if last_rank.is_disjoint(one) {
+                    moves.push(Move::new(from, one.lsb(), MoveType::Quiet));
+                    let two = one.shift(forward);
+                    if !start_rank.is_disjoint(coord) && occupied.is_disjoint(two) {
+                        moves.push(Move::new(from, two.lsb(), MoveType::DoublePush));
+                    }
+                } else {
+                    for p in PROMOTIONS {
+                        moves.push(Move::new(from, one.lsb(), MoveType::Promote(p)));
+                    }
+                }
+            }
+
+            let attacks = pawn_attacks(from, us);
+            for to in attacks & enemy {
+                if last_rank.is_disjoint(Bitboard::get_coord(to)) {
+                    moves.push(Move::new(from, to, MoveType::Capture));
+                } else {
+                    for p in PROMOTIONS {
+                        moves.push(Move::new(from, to, MoveType::PromoteCapture(p)));
+                    }
+                }
+            }
+
+            if let Some(ep) = ep {
+                if !attacks.is_disjoint(Bitboard::get_coord(ep)) {
+                    moves.push(Move::new(from, ep, MoveType::EnPassant));
+                }
+            }
+        }
+    }
+
+    // Chess960 rules, which cover the standard game: the king and the castling rook
+    // jump to their usual squares, everything they pass over or land on other than
+    // themselves has to be empty, and the king may not start on, cross or land on an
+    // attacked square. The move is written as the king taking its rook.
+    fn castling_moves(&self, moves: &mut Vec<Move>, occupied: Bitboard) {
+        let us = self.to_move;
+        let rank = us.back_rank();
+        let king = match self.pieces.castling_king_file(us) {
+            Some(f) => Square::from_rank_file(rank, f),
+            None => return,
+        };
+        let rooks = self.pieces.get(us, Piece::R);
+
+        for wing in WINGS {
+            let rook = match self.castling.get(us, wing) {
+                Some(f) => Square::from_rank_file(rank, f),
+                None => continue,
+            };
+            if rooks.is_disjoint(Bitboard::get_coord(rook)) {
+                continue;
+            }
+            let king_to = Square::from_rank_file(rank, wing.king_file());
+            let rook_to = Square::from_rank_file(rank, wing.rook_file());
+            let movers = Bitboard::get_coord(king) | Bitboard::get_coord(rook);
+            let king_path = between(king, king_to) | Bitboard::get_coord(king_to);
+            let rook_path = between(rook, rook_to) | Bitboard::get_coord(rook_to);
+            // With both movers lifted, so a slider behind the rook is seen
+            let others = occupied & !movers;
+            if !(king_path | rook_path).is_disjoint(others) {
+                continue;
+            }
+            let attacked = |s: Square| is_attacked_by(&self.pieces, s, us.opposite(), others);
+            if attacked(king) || king_path.into_iter().any(attacked) {
+                continue;
+            }
+            moves.push(Move::new(king, rook, wing.castle_kind()));
+        }
+    }
+}
+
+fn push_moves(moves: &mut Vec<Move>, from: Square, targets: Bitboard, enemy: Bitboard) {
+    for to in targets {
+        let kind = if enemy.is_disjoint(Bitboard::get_coord(to)) {
+            MoveType::Quiet
+        } else {
+            MoveType::Capture
+        };
+        moves.push(Move::new(from, to, kind));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+
+    fn count_kind(moves: &[Move], f: fn(MoveType) -> bool) -> usize {
+        moves.iter().filter(|m| f(m.kind())).count()
+    }
+
+    #[test]
+    fn start_position_has_20_moves() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let moves = parse_fen(fen.to_string()).unwrap().pseudo_legal_moves();
+        assert!(moves.len() == 20);
+        assert!(count_kind(&moves, |k| k == MoveType::DoublePush) == 8);
+    }
+
+    #[test]
+    fn black_start_position_has_20_moves() {
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        let moves = parse_fen(fen.to_string()).unwrap().pseudo_legal_moves();
+        assert!(moves.len() == 20);
+    }
+
+    #[test]
+    fn kiwipete_has_48_moves() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let moves = parse_fen(fen.to_string()).unwrap().pseudo_legal_moves();
+        assert!(moves.len() == 48);
+        assert!(count_kind(&moves, |k| k == MoveType::Capture) == 8);
+        assert!(count_kind(&moves, |k| k == MoveType::CastleKing) == 1);
+        assert!(count_kind(&moves, |k| k == MoveType::CastleQueen) == 1);
+    }
+
+    #[test]
+    fn promotions_and_promotion_captures() {
+        let fen = "1n5k/P7/8/8/8/8/8/7K w - - 0 1";
+        let moves = parse_fen(fen.to_string()).unwrap().pseudo_legal_moves();
+        assert!(count_kind(&moves, |k| matches!(k, MoveType::Promote(_))) == 4);
+        assert!(count_kind(&moves, |k| matches!(k, MoveType::PromoteCapture(_))) == 4);
+    }
+
+    #[test]
+    fn en_passant_capture() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        board.en_passant = Some(Square::from_rank_file(Rank::R6, File::D));
+        let moves = board.pseudo_legal_moves();
+        let ep = moves
+            .iter()
+            .find(|m| m.kind() == MoveType::EnPassant)
+            .unwrap();
+        assert!(ep.from() == Square::from_rank_file(Rank::R5, File::E));
+        assert!(ep.to() == Square::from_rank_file(Rank::R6, File::D));
+    }
+
+    fn legal(fen: &str) -> Vec<Move> {
+        parse_fen(fen.to_string()).unwrap().legal_moves()
+    }
+
+    #[test]
+    fn legal_start_position_has_20_moves() {
+        assert!(legal("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").len() == 20);
+    }
+
+    #[test]
+    fn legal_kiwipete_has_48_moves() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert!(legal(fen).len() == 48);
+    }
+
+    #[test]
+    fn pinned_piece_cannot_leave_the_line() {
+        let moves = legal("4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1");
+        assert!(moves.len() == 4);
+        assert!(moves.iter().all(|m| m.from() == Square::new(4)));
+    }
+
+    #[test]
+    fn pinned_slider_may_move_along_the_pin() {
+        let moves = legal("4k3/4r3/8/8/8/8/4R3/4K3 w - - 0 1");
+        let rook_moves = moves.iter().filter(|m| m.from() == Square::new(12)).count();
+        // e3 to e6 and the capture on e7
+        assert!(rook_moves == 5);
+    }
+
+    #[test]
+    fn single_check_must_be_blocked_captured_or_evaded() {
+        // Re8 checks: the bishop and knight may only land on the e-file
+        let moves = legal("4r2k/8/5N2/8/8/3B4/8/4K3 w - - 0 1");
+        for m in &moves {
+            assert!(m.from() == Square::new(4) || m.to().file_index() == 4);
+        }
+        assert!(moves
+            .iter()
+            .any(|m| m.kind() == MoveType::Capture && m.from() == Square::new(45)));
+    }
+
+    #[test]
+    fn double_check_allows_only_king_moves() {
+        let moves = legal("4k3/8/8/8/1b6/8/4r3/R3K2R w KQ - 0 1");
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|m| m.from() == Square::new(4)));
+    }
+
+    #[test]
+    fn king_cannot_retreat_along_checking_ray() {
+        let moves = legal("4k3/8/8/8/8/8/8/r3K3 w - - 0 1");
+        assert!(moves.iter().all(|m| m.to().rank_index() != 0));
+    }
+
+    #[test]
+    fn en_passant_discovered_check_is_illegal() {
+        let fen = "8/8/8/K2pP2r/8/8/8/7k w - - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        board.en_passant = Some(Square::from_rank_file(Rank::R6, File::D));
+        assert!(board
+            .pseudo_legal_moves()
+            .iter()
+            .any(|m| m.kind() == MoveType::EnPassant));
+        assert!(!board
+            .legal_moves()
+            .iter()
+            .any(|m| m.kind() == MoveType::EnPassant));
+    }
+
+    #[test]
+    fn en_passant_may_capture_the_checker() {
+        let fen = "8/8/8/3pP3/4K3/8/8/7k w - - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        board.en_passant = Some(Square::from_rank_file(Rank::R6, File::D));
+        assert!(board.in_check());
+        assert!(board
+            .legal_moves()
+            .iter()
+            .any(|m| m.kind() == MoveType::EnPassant));
+    }
+
+    #[test]
+    fn has_legal_en_passant_agrees_with_the_moves() {
+        fn walk(board: &mut BbBoardState, depth: u8) {
+            let moves = board.legal_moves();
+            let listed = moves.iter().any(|m| m.kind() == MoveType::EnPassant);
+            assert!(board.has_legal_en_passant() == listed, "{}", board.to_fen());
+            if depth == 0 {
+                return;
+            }
+            for m in moves {
+                let undo = board.make_move(m);
+                walk(board, depth - 1);
+                board.unmake_move(m, undo);
+            }
+        }
+        // Rook and king on one rank make some captures illegal
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        walk(&mut parse_fen(fen.to_string()).unwrap(), 3);
+    }
+
+    #[test]
+    fn no_castling_out_of_check() {
+        let moves = legal("4k3/8/8/8/8/8/4r3/R3K2R w KQ - 0 1");
+        assert!(!moves
+            .iter()
+            .any(|m| matches!(m.kind(), MoveType::CastleKing | MoveType::CastleQueen)));
+    }
+
+    #[test]
+    fn checkmate_has_no_legal_moves() {
+        let fen = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert!(legal(fen).is_empty());
+    }
+
+    #[test]
+    fn no_castling_through_attacked_square() {
+        let fen = "4k3/8/8/8/8/8/5r2/R3K2R w KQ - 0 1";
+        let moves = parse_fen(fen.to_string()).unwrap().pseudo_legal_moves();
+        assert!(count_kind(&moves, |k| k == MoveType::CastleKing) == 0);
+        assert!(count_kind(&moves, |k| k == MoveType::CastleQueen) == 1);
+    }
+}
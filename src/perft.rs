@@ -0,0 +1,310 @@
+use std::ops::AddAssign;
+
+use crate::api::{Board, Move, MoveType};
+use crate::bitboard::BbBoardState;
+
+// Leaf counts broken down the same way as the published perft tables
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub en_passants: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    pub mates: u64,
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.captures += rhs.captures;
+        self.en_passants += rhs.en_passants;
+        self.castles += rhs.castles;
+        self.promotions += rhs.promotions;
+        self.checks += rhs.checks;
+        self.mates += rhs.mates;
+    }
+}
+
+// Number of leaf nodes of the legal move tree `depth` plies deep
+pub fn perft(board: &mut BbBoardState, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = board.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for m in moves {
+        let undo = board.make_move(m);
+        nodes += perft(board, depth - 1);
+        board.unmake_move(m, undo);
+    }
+    nodes
+}
+
+// Like `perft`, but classifies the moves played on the last ply
+pub fn perft_stats(board: &mut BbBoardState, depth: u8) -> PerftStats {
+    let mut stats = PerftStats::default();
+    if depth == 0 {
+        stats.nodes = 1;
+        return stats;
+    }
+    for m in board.legal_moves() {
+        let undo = board.make_move(m);
+        if depth == 1 {
+            stats += leaf_stats(board, m);
+        } else {
+            stats += perft_stats(board, depth - 1);
+        }
+        board.unmake_move(m, undo);
+    }
+    stats
+}
+
+// Stats for a single leaf, called with `m` already made
+fn leaf_stats(board: &BbBoardState, m: Move) -> PerftStats {
+    let mut stats = PerftStats {
+        nodes: 1,
+        ..Default::default()
+    };
+    match m.kind() {
+        MoveType::Capture => stats.captures = 1,
+        MoveType::EnPassant => {
+            stats.captures = 1;
+            stats.en_passants = 1;
+        }
+        MoveType::CastleKing | MoveType::CastleQueen => stats.castles = 1,
+        MoveType::Promote(_) => stats.promotions = 1,
+        MoveType::PromoteCapture(_) => {
+            stats.captures = 1;
+            stats.promotions = 1;
+        }
+        MoveType::Quiet | MoveType::DoublePush => (),
+    }
+    if board.in_check() {
+        stats.checks = 1;
+        if board.legal_moves().is_empty() {
+            stats.mates = 1;
+        }
+    }
+    stats
+}
+
+// Perft node count below each root move
+pub fn divide(board: &mut BbBoardState, depth: u8) -> Vec<(Move, u64)> {
+    let mut counts = Vec::new();
+    if depth == 0 {
+        return counts;
+    }
+    for m in board.legal_moves() {
+        let undo = board.make_move(m);
+        counts.push((m, perft(board, depth - 1)));
+        board.unmake_move(m, undo);
+    }
+    counts
+}
+
+// Perft stats below each root move
+pub fn divide_stats(board: &mut BbBoardState, depth: u8) -> Vec<(Move, PerftStats)> {
+    let mut counts = Vec::new();
+    if depth == 0 {
+        return counts;
+    }
+    for m in board.legal_moves() {
+        let undo = board.make_move(m);
+        let stats = if depth == 1 {
+            leaf_stats(board, m)
+        } else {
+            perft_stats(board, depth - 1)
+        };
+        counts.push((m, stats));
+        board.unmake_move(m, undo);
+    }
+    counts
+}
+
+// Print one line per root move, in the format other engines use, followed by the total.
+// With `breakdown` the captures, en passants, castles, promotions, checks and mates
+// are printed as well.
+pub fn print_divide(board: &mut BbBoardState, depth: u8, breakdown: bool) {
+    if breakdown {
+        let mut total = PerftStats::default();
+        for (m, stats) in divide_stats(board, depth) {
+            println!("{}: {}", m, format_stats(&stats));
+            total += stats;
+        }
+        println!();
+        println!("Total: {}", format_stats(&total));
+    } else {
+        let mut total = 0;
+        for (m, nodes) in divide(board, depth) {
+            println!("{}: {}", m, nodes);
+            total += nodes;
+        }
+        println!();
+        println!("Nodes searched: {}", total);
+    }
+}
+
+fn format_stats(stats: &PerftStats) -> String {
+    format!(
+        "{} (captures {}, e.p. {}, castles {}, promotions {}, checks {}, mates {})",
+        stats.nodes,
+        stats.captures,
+        stats.en_passants,
+        stats.castles,
+        stats.promotions,
+        stats.checks,
+        stats.mates
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::perft::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_4_MIRRORED: &str =
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str =
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    fn check_perft(fen: &str, expected: &[u64]) {
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        for (i, &nodes) in expected.iter().enumerate() {
+            let depth = i as u8 + 1;
+            let actual = perft(&mut board, depth);
+            assert!(
+                actual == nodes,
+                "{} depth {}: expected {} got {}",
+                fen,
+                depth,
+                nodes,
+                actual
+            );
+        }
+    }
+
+    fn stats(
+        nodes: u64,
+        captures: u64,
+        en_passants: u64,
+        castles: u64,
+        promotions: u64,
+        checks: u64,
+        mates: u64,
+    ) -> PerftStats {
+        PerftStats {
+            nodes,
+            captures,
+            en_passants,
+            castles,
+            promotions,
+            checks,
+            mates,
+        }
+    }
+
+    fn check_stats(fen: &str, depth: u8, expected: PerftStats) {
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let actual = perft_stats(&mut board, depth);
+        assert!(
+            actual == expected,
+            "{} depth {}: expected {:?} got {:?}",
+            fen,
+            depth,
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn perft_start() {
+        check_perft(START, &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        check_perft(KIWIPETE, &[48, 2039, 97862]);
+    }
+
+    #[test]
+    fn perft_position_3() {
+        check_perft(POSITION_3, &[14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn perft_position_4() {
+        check_perft(POSITION_4, &[6, 264, 9467]);
+        check_perft(POSITION_4_MIRRORED, &[6, 264, 9467]);
+    }
+
+    #[test]
+    fn perft_position_5() {
+        check_perft(POSITION_5, &[44, 1486, 62379]);
+    }
+
+    #[test]
+    fn perft_position_6() {
+        check_perft(POSITION_6, &[46, 2079, 89890]);
+    }
+
+    #[test]
+    fn perft_stats_start() {
+        check_stats(START, 3, stats(8902, 34, 0, 0, 0, 12, 0));
+    }
+
+    #[test]
+    fn perft_stats_kiwipete() {
+        check_stats(KIWIPETE, 1, stats(48, 8, 0, 2, 0, 0, 0));
+        check_stats(KIWIPETE, 2, stats(2039, 351, 1, 91, 0, 3, 0));
+    }
+
+    #[test]
+    fn perft_stats_position_3() {
+        check_stats(POSITION_3, 3, stats(2812, 209, 2, 0, 0, 267, 0));
+    }
+
+    #[test]
+    fn perft_stats_position_4() {
+        check_stats(POSITION_4, 3, stats(9467, 1021, 4, 0, 120, 38, 22));
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut board = parse_fen(KIWIPETE.to_string()).unwrap();
+        let counts = divide(&mut board, 2);
+        assert!(counts.len() == 48);
+        assert!(counts.iter().map(|(_, n)| n).sum::<u64>() == 2039);
+        let stats = divide_stats(&mut board, 2);
+        assert!(stats.iter().map(|(_, s)| s.nodes).sum::<u64>() == 2039);
+    }
+
+    #[test]
+    fn move_names() {
+        let mut board = parse_fen(POSITION_4.to_string()).unwrap();
+        let names: Vec<String> = divide(&mut board, 1)
+            .into_iter()
+            .map(|(m, _)| m.to_string())
+            .collect();
+        assert!(names.contains(&"g1h1".to_string()));
+        assert!(names.contains(&"f1f2".to_string()));
+
+        let fen = "4k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let names: Vec<String> = divide(&mut board, 1)
+            .into_iter()
+            .map(|(m, _)| m.to_string())
+            .collect();
+        assert!(names.contains(&"b7b8q".to_string()));
+        assert!(names.contains(&"b7b8n".to_string()));
+    }
+}
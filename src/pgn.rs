@@ -0,0 +1,913 @@
+use std::io::BufRead;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::api::{Board, Move, Side};
+use crate::bitboard::{parse_fen, BbBoardState, FenError};
+use crate::san::SanError;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Tags every exported game starts with, in this order, and their unknown values
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+const LINE_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PgnResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    // `*`, a game still going or abandoned
+    Unknown,
+}
+
+impl PgnResult {
+    pub fn token(&self) -> &'static str {
+        match self {
+            PgnResult::WhiteWins => "1-0",
+            PgnResult::BlackWins => "0-1",
+            PgnResult::Draw => "1/2-1/2",
+            PgnResult::Unknown => "*",
+        }
+    }
+
+    pub fn from_token(s: &str) -> Option<Self> {
+        match s {
+            "1-0" => Some(PgnResult::WhiteWins),
+            "0-1" => Some(PgnResult::BlackWins),
+            "1/2-1/2" => Some(PgnResult::Draw),
+            "*" => Some(PgnResult::Unknown),
+            _ => None,
+        }
+    }
+}
+
+// Evaluation from an `[%eval]` command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PgnEval {
+    // From White's point of view
+    Pawns(f64),
+    // Moves to mate, negative when Black mates
+    Mate(i32),
+}
+
+// A `{...}` or `;` comment. Embedded commands like `[%clk 0:03:00]` are split out
+// from the text.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    // Name and arguments of each `[%name args]`
+    pub commands: Vec<(String, String)>,
+}
+
+impl Comment {
+    pub fn parse(s: &str) -> Self {
+        let mut commands = Vec::new();
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("[%") {
+            let end = match rest[start..].find(']') {
+                Some(end) => start + end,
+                None => break,
+            };
+            text.push_str(&rest[..start]);
+            text.push(' ');
+            let command = rest[start + 2..end].trim();
+            let (name, args) = command
+                .split_once(char::is_whitespace)
+                .unwrap_or((command, ""));
+            commands.push((name.to_string(), args.trim().to_string()));
+            rest = &rest[end + 1..];
+        }
+        text.push_str(rest);
+        Comment {
+            // Whitespace is collapsed so comments can be rewrapped
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            commands,
+        }
+    }
+
+    pub fn command(&self, name: &str) -> Option<&str> {
+        self.commands
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, args)| args.as_str())
+    }
+
+    // Remaining clock time in seconds from `[%clk h:mm:ss]`
+    pub fn clock(&self) -> Option<f64> {
+        let mut seconds = 0.0;
+        for part in self.command("clk")?.split(':') {
+            seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+        }
+        Some(seconds)
+    }
+
+    // `[%eval 0.17]` or `[%eval #-3]`, optionally followed by `,depth`
+    pub fn eval(&self) -> Option<PgnEval> {
+        let eval = self.command("eval")?.split(',').next()?.trim();
+        match eval.strip_prefix('#') {
+            Some(mate) => mate.parse().ok().map(PgnEval::Mate),
+            None => eval.parse().ok().map(PgnEval::Pawns),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnMove {
+    pub m: Move,
+    pub nags: Vec<u8>,
+    // Comments following the move
+    pub comments: Vec<Comment>,
+    // Alternatives to this move, each starting from the position before it
+    pub variations: Vec<PgnLine>,
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> Self {
+        PgnMove {
+            m,
+            nags: Vec::new(),
+            comments: Vec::new(),
+            variations: Vec::new(),
+        }
+    }
+}
+
+// The mainline or a variation
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PgnLine {
+    // Comments before the first move
+    pub comments: Vec<Comment>,
+    pub moves: Vec<PgnMove>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnGame {
+    // In the order they were read
+    pub tags: Vec<(String, String)>,
+    // From the `FEN` tag, or the standard start position
+    pub start: BbBoardState,
+    pub mainline: PgnLine,
+    pub result: PgnResult,
+}
+
+impl PgnGame {
+    pub fn new(start: BbBoardState) -> Self {
+        PgnGame {
+            tags: Vec::new(),
+            start,
+            mainline: PgnLine::default(),
+            result: PgnResult::Unknown,
+        }
+    }
+
+    // Replaces the value if the tag is already there
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn moves(&self) -> Vec<Move> {
+        self.mainline.moves.iter().map(|pm| pm.m).collect()
+    }
+
+    // Position after the last mainline move
+    pub fn end(&self) -> BbBoardState {
+        let mut board = self.start.clone();
+        for m in self.moves() {
+            board.make_move(m);
+        }
+        board
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgnErrorKind {
+    Io(std::io::ErrorKind),
+    BadTag,
+    BadFen(FenError),
+    BadMove(SanError),
+    // A token where it can't go, like `)` outside a variation or a NAG before any move
+    UnexpectedToken(String),
+    // Unclosed comment or variation
+    UnexpectedEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnError {
+    // Line the game starts on, counting from 1
+    pub line: usize,
+    pub kind: PgnErrorKind,
+}
+
+impl std::fmt::Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "game at line {}: ", self.line)?;
+        match &self.kind {
+            PgnErrorKind::Io(e) => write!(f, "read failed: {}", e),
+            PgnErrorKind::BadTag => write!(f, "malformed tag pair"),
+            PgnErrorKind::BadFen(e) => write!(f, "bad FEN tag: {}", e),
+            PgnErrorKind::BadMove(e) => write!(f, "{}", e),
+            PgnErrorKind::UnexpectedToken(t) => write!(f, "unexpected '{}'", t),
+            PgnErrorKind::UnexpectedEnd => write!(f, "unclosed comment or variation"),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+// Reads games one at a time. A broken game gives an error for that game only;
+// reading carries on with the next one.
+pub struct PgnReader<R> {
+    lines: std::io::Lines<R>,
+    line: usize,
+    // First line of the next game, read while looking for the end of the last one
+    pending: Option<(usize, String)>,
+    done: bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        PgnReader {
+            lines: reader.lines(),
+            line: 0,
+            pending: None,
+            done: false,
+        }
+    }
+
+    // Lines of the next game and the line it starts on. A game ends where a tag
+    // pair follows movetext, or inside an unclosed comment where one follows a
+    // blank line, so a broken game doesn't take the rest of the file with it.
+    fn next_chunk(&mut self) -> Option<Result<(usize, String), PgnError>> {
+        let mut text = String::new();
+        let mut start = 0;
+        let mut in_movetext = false;
+        let mut in_comment = false;
+        let mut after_blank = false;
+        loop {
+            let (number, line) = match self.pending.take() {
+                Some(pending) => pending,
+                None => match self.lines.next() {
+                    Some(Ok(line)) => {
+                        self.line += 1;
+                        (self.line, line)
+                    }
+                    Some(Err(e)) => {
+                        self.done = true;
+                        return Some(Err(PgnError {
+                            line: self.line + 1,
+                            kind: PgnErrorKind::Io(e.kind()),
+                        }));
+                    }
+                    None => break,
+                },
+            };
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && in_movetext && (!in_comment || after_blank) {
+                self.pending = Some((number, line));
+                break;
+            }
+            after_blank = trimmed.is_empty();
+            if !in_comment {
+                // Escaped lines are for other programs
+                if line.starts_with('%') {
+                    continue;
+                }
+                if text.is_empty() && trimmed.is_empty() {
+                    continue;
+                }
+            }
+            if text.is_empty() {
+                start = number;
+            }
+            if in_comment || !trimmed.starts_with('[') {
+                in_movetext |= !trimmed.is_empty();
+                for c in line.chars() {
+                    match c {
+                        '}' if in_comment => in_comment = false,
+                        '{' if !in_comment => in_comment = true,
+                        ';' if !in_comment => break,
+                        _ => (),
+                    }
+                }
+            }
+            text.push_str(&line);
+            text.push('\n');
+        }
+        if text.is_empty() {
+            None
+        } else {
+            Some(Ok((start, text)))
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        Some(
+            self.next_chunk()?
+                .and_then(|(line, text)| parse_game(&text).map_err(|kind| PgnError { line, kind })),
+        )
+    }
+}
+
+impl Comment {
+    fn to_pgn(&self) -> String {
+        let mut parts: Vec<String> = self
+            .commands
+            .iter()
+            .map(|(name, args)| match args.is_empty() {
+                true => format!("[%{}]", name),
+                false => format!("[%{} {}]", name, args),
+            })
+            .collect();
+        if !self.text.is_empty() {
+            parts.push(self.text.clone());
+        }
+        format!("{{{}}}", parts.join(" "))
+    }
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Movetext of a line as words, starting from `board`. A move number goes before
+// every White move, and before a Black move that follows a comment or variation.
+fn line_words(line: &PgnLine, mut board: BbBoardState, words: &mut Vec<String>) {
+    let comment_words = |c: &Comment, words: &mut Vec<String>| {
+        words.extend(c.to_pgn().split(' ').map(str::to_string));
+    };
+    for c in &line.comments {
+        comment_words(c, words);
+    }
+    let mut number_black = true;
+    for pm in &line.moves {
+        let number = board.fullmove_number();
+        match board.to_move {
+            Side::White => words.push(format!("{}.", number)),
+            Side::Black if number_black => words.push(format!("{}...", number)),
+            Side::Black => (),
+        }
+        words.push(board.to_san(pm.m));
+        words.extend(pm.nags.iter().map(|nag| format!("${}", nag)));
+        for c in &pm.comments {
+            comment_words(c, words);
+        }
+        for variation in &pm.variations {
+            let mut inner = Vec::new();
+            line_words(variation, board.clone(), &mut inner);
+            match inner.is_empty() {
+                true => words.push("()".to_string()),
+                false => {
+                    inner[0].insert(0, '(');
+                    inner.last_mut().unwrap().push(')');
+                    words.extend(inner);
+                }
+            }
+        }
+        number_black = !pm.comments.is_empty() || !pm.variations.is_empty();
+        board.make_move(pm.m);
+    }
+}
+
+// Fill lines up to `LINE_WIDTH` columns
+fn wrap(words: &[String]) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for word in words {
+        if width > 0 && width + 1 + word.len() > LINE_WIDTH {
+            out.push('\n');
+            width = 0;
+        } else if width > 0 {
+            out.push(' ');
+            width += 1;
+        }
+        out.push_str(word);
+        width += word.len();
+    }
+    out.push('\n');
+    out
+}
+
+impl PgnGame {
+    // Export format: the Seven Tag Roster in order with `?` for anything unknown,
+    // then the other tags as read, then the movetext wrapped at 80 columns. A game
+    // not starting from the standard position gets `SetUp` and `FEN` tags.
+    pub fn to_pgn(&self) -> String {
+        let mut tags: Vec<(String, String)> = SEVEN_TAG_ROSTER
+            .iter()
+            .map(|(name, unknown)| {
+                let value = match *name {
+                    "Result" => self.result.token(),
+                    _ => self.tag(name).unwrap_or(unknown),
+                };
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+        let mut extra = PgnGame::new(self.start.clone());
+        for (name, value) in &self.tags {
+            if !SEVEN_TAG_ROSTER.iter().any(|(r, _)| r == name) {
+                extra.set_tag(name, value);
+            }
+        }
+        let fen = self.start.to_fen();
+        if fen != START_FEN {
+            extra.set_tag("SetUp", "1");
+            extra.set_tag("FEN", &fen);
+        }
+        tags.extend(extra.tags);
+
+        let mut pgn = String::new();
+        for (name, value) in tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(&value)));
+        }
+        pgn.push('\n');
+
+        let mut words = Vec::new();
+        line_words(&self.mainline, self.start.clone(), &mut words);
+        words.push(self.result.token().to_string());
+        pgn.push_str(&wrap(&words));
+        pgn
+    }
+}
+
+// Games separated by blank lines
+pub fn write_pgn(games: &[PgnGame]) -> String {
+    games
+        .iter()
+        .map(PgnGame::to_pgn)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Every game in a string
+pub fn parse_pgn(s: &str) -> Vec<Result<PgnGame, PgnError>> {
+    PgnReader::new(s.as_bytes()).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(Comment),
+    Open,
+    Close,
+    Nag(u8),
+    Result(PgnResult),
+    San(String),
+}
+
+impl Token {
+    // Enough of the token to point at it in an error
+    fn text(&self) -> String {
+        match self {
+            Token::Tag(name, _) => format!("[{}", name),
+            Token::Comment(_) => "{".to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+            Token::Nag(nag) => format!("${}", nag),
+            Token::Result(result) => result.token().to_string(),
+            Token::San(san) => san.clone(),
+        }
+    }
+}
+
+// Suffix annotations and the NAGs they stand for
+const ANNOTATIONS: [(&str, u8); 6] = [
+    ("!", 1),
+    ("?", 2),
+    ("!!", 3),
+    ("??", 4),
+    ("!?", 5),
+    ("?!", 6),
+];
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "{}();[]$".contains(c)
+}
+
+fn read_until(chars: &mut Peekable<Chars>, end: char) -> Result<String, PgnErrorKind> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == end => return Ok(s),
+            Some(c) => s.push(c),
+            None => return Err(PgnErrorKind::UnexpectedEnd),
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn read_tag(chars: &mut Peekable<Chars>) -> Result<Token, PgnErrorKind> {
+    skip_whitespace(chars);
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    skip_whitespace(chars);
+    if name.is_empty() || chars.next() != Some('"') {
+        return Err(PgnErrorKind::BadTag);
+    }
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => value.push(chars.next().ok_or(PgnErrorKind::BadTag)?),
+            Some('"') => break,
+            Some('\n') | None => return Err(PgnErrorKind::BadTag),
+            Some(c) => value.push(c),
+        }
+    }
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(']') => Ok(Token::Tag(name, value)),
+        _ => Err(PgnErrorKind::BadTag),
+    }
+}
+
+// A move number, a result, or a SAN move with any `!?` annotation split off as a NAG
+fn push_symbol(tokens: &mut Vec<Token>, symbol: &str) -> Result<(), PgnErrorKind> {
+    if let Some(result) = PgnResult::from_token(symbol) {
+        tokens.push(Token::Result(result));
+        return Ok(());
+    }
+    // `12.`, `12...` or `1.e4`, but not castling written with zeros
+    let mut san = symbol;
+    let after_number = symbol.trim_start_matches(|c: char| c.is_ascii_digit());
+    if after_number.len() < symbol.len() && !symbol.starts_with("0-0") {
+        san = after_number.trim_start_matches('.');
+        if san.len() == after_number.len() {
+            return Err(PgnErrorKind::UnexpectedToken(symbol.to_string()));
+        }
+    }
+    if san.is_empty() {
+        return Ok(());
+    }
+    let body = san.trim_end_matches(['!', '?']);
+    tokens.push(Token::San(body.to_string()));
+    let annotation = &san[body.len()..];
+    if let Some((_, nag)) = ANNOTATIONS.iter().find(|(a, _)| *a == annotation) {
+        tokens.push(Token::Nag(*nag));
+    }
+    Ok(())
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, PgnErrorKind> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '[' => tokens.push(read_tag(&mut chars)?),
+            '{' => tokens.push(Token::Comment(Comment::parse(&read_until(
+                &mut chars, '}',
+            )?))),
+            ';' => {
+                let mut text = String::new();
+                while let Some(c) = chars.next_if(|c| *c != '\n') {
+                    text.push(c);
+                }
+                tokens.push(Token::Comment(Comment::parse(&text)));
+            }
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '$' => {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                let nag = digits
+                    .parse()
+                    .map_err(|_| PgnErrorKind::UnexpectedToken(format!("${}", digits)))?;
+                tokens.push(Token::Nag(nag));
+            }
+            ']' | '}' => return Err(PgnErrorKind::UnexpectedToken(c.to_string())),
+            c => {
+                let mut symbol = c.to_string();
+                while let Some(c) = chars.next_if(|c| !is_delimiter(*c)) {
+                    symbol.push(c);
+                }
+                push_symbol(&mut tokens, &symbol)?;
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+// Moves up to the end of the variation or game, with `board` the position before
+// the first of them
+fn parse_line(tokens: &mut Tokens, mut board: BbBoardState) -> Result<PgnLine, PgnErrorKind> {
+    let mut line = PgnLine::default();
+    // Where a variation on the last move starts from
+    let mut before = board.clone();
+    loop {
+        match tokens.peek() {
+            None | Some(Token::Close) | Some(Token::Result(_)) => return Ok(line),
+            _ => (),
+        }
+        match tokens.next().unwrap() {
+            Token::Comment(c) => match line.moves.last_mut() {
+                Some(last) => last.comments.push(c),
+                None => line.comments.push(c),
+            },
+            Token::Nag(nag) => match line.moves.last_mut() {
+                Some(last) => last.nags.push(nag),
+                None => return Err(PgnErrorKind::UnexpectedToken(Token::Nag(nag).text())),
+            },
+            Token::Open => {
+                let variation = parse_line(tokens, before.clone())?;
+                if tokens.next() != Some(Token::Close) {
+                    return Err(PgnErrorKind::UnexpectedEnd);
+                }
+                match line.moves.last_mut() {
+                    Some(last) => last.variations.push(variation),
+                    None => return Err(PgnErrorKind::UnexpectedToken(Token::Open.text())),
+                }
+            }
+            Token::San(san) => {
+                let m = board.parse_san(&san).map_err(PgnErrorKind::BadMove)?;
+                before = board.clone();
+                board.make_move(m);
+                line.moves.push(PgnMove::new(m));
+            }
+            t => return Err(PgnErrorKind::UnexpectedToken(t.text())),
+        }
+    }
+}
+
+fn parse_game(text: &str) -> Result<PgnGame, PgnErrorKind> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut tags = Vec::new();
+    while let Some(Token::Tag(name, value)) = tokens.next_if(|t| matches!(t, Token::Tag(..))) {
+        tags.push((name, value));
+    }
+
+    let fen = tags
+        .iter()
+        .find(|(n, _)| n == "FEN")
+        .map_or(START_FEN, |(_, v)| v.as_str());
+    let start = parse_fen(fen.to_string()).map_err(PgnErrorKind::BadFen)?;
+    let mainline = parse_line(&mut tokens, start.clone())?;
+
+    // A missing result token is tolerated, falling back on the tag
+    let result = match tokens.next() {
+        Some(Token::Result(result)) => result,
+        Some(t) => return Err(PgnErrorKind::UnexpectedToken(t.text())),
+        None => tags
+            .iter()
+            .find(|(n, _)| n == "Result")
+            .and_then(|(_, v)| PgnResult::from_token(v))
+            .unwrap_or(PgnResult::Unknown),
+    };
+    if let Some(t) = tokens.next() {
+        return Err(PgnErrorKind::UnexpectedToken(t.text()));
+    }
+    Ok(PgnGame {
+        tags,
+        start,
+        mainline,
+        result,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::pgn::*;
+
+    const GAMES: &str = r#"[Event "Casual"]
+[Site "?"]
+[Date "2024.01.02"]
+[Round "-"]
+[White "A \"Quoted\" Player"]
+[Black "B"]
+[Result "1-0"]
+
+{Opening comment} 1. e4 e5 2. Nf3 {[%clk 0:03:00] [%eval 0.25] Main move} Nc6
+(2... d6 $6 3. d4 (3. Bc4 Be7) 3... exd4) 3. Bb5! a6?! 4. Ba4 Nf6 5. O-O
+; rest of line comment
+Be7 1-0
+
+% escaped line, ignored
+[Event "Broken"]
+[Result "*"]
+
+1. e4 e5 2. Ke3 Nc6 *
+
+[Event "Setup"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+[Result "1/2-1/2"]
+
+1.e4 Kd7 2.Kf2 0-1
+"#;
+
+    #[test]
+    fn reads_all_games_and_skips_broken_ones() {
+        let games = parse_pgn(GAMES);
+        assert!(games.len() == 3);
+        assert!(games[0].is_ok());
+        let e = games[1].clone().unwrap_err();
+        assert!(e.line == 15);
+        assert!(matches!(e.kind, PgnErrorKind::BadMove(_)));
+        assert!(e.to_string() == "game at line 15: 'Ke3' is not a legal move");
+        let setup = games[2].clone().unwrap();
+        assert!(setup.tag("Event") == Some("Setup"));
+        assert!(setup.moves().len() == 3);
+        // The result token wins over the tag
+        assert!(setup.result == PgnResult::BlackWins);
+    }
+
+    #[test]
+    fn tags_moves_and_result() {
+        let game = parse_pgn(GAMES).remove(0).unwrap();
+        assert!(game.tags.len() == 7);
+        assert!(game.tag("White") == Some("A \"Quoted\" Player"));
+        assert!(game.result == PgnResult::WhiteWins);
+        let moves = game.moves();
+        assert!(moves.len() == 10);
+        assert!(moves[0].to_string() == "e2e4");
+        assert!(moves[8].kind() == MoveType::CastleKing);
+        assert!(
+            game.end().to_fen()
+                == "r1bqk2r/1pppbppp/p1n2n2/4p3/B3P3/5N2/PPPP1PPP/RNBQ1RK1 w kq - 4 6"
+        );
+    }
+
+    #[test]
+    fn comments_nags_and_variations() {
+        let game = parse_pgn(GAMES).remove(0).unwrap();
+        let line = &game.mainline;
+        assert!(line.comments[0].text == "Opening comment");
+
+        let nf3 = &line.moves[2];
+        assert!(nf3.comments[0].text == "Main move");
+        assert!(nf3.comments[0].clock() == Some(180.0));
+        assert!(nf3.comments[0].eval() == Some(PgnEval::Pawns(0.25)));
+
+        let nc6 = &line.moves[3];
+        assert!(nc6.variations.len() == 1);
+        let var = &nc6.variations[0];
+        assert!(var.moves.len() == 3);
+        assert!(var.moves[0].nags == vec![6]);
+        // 3. Bc4 Be7 replaces 3. d4 inside the variation
+        assert!(var.moves[1].variations[0].moves.len() == 2);
+        assert!(var.moves[1].variations[0].moves[0].m.to_string() == "f1c4");
+
+        assert!(line.moves[4].nags == vec![1]);
+        assert!(line.moves[5].nags == vec![6]);
+        assert!(line.moves[8].comments[0].text == "rest of line comment");
+    }
+
+    #[test]
+    fn comment_commands() {
+        let c = Comment::parse("  before [%eval #-3] [%clk 1:02:03.5]\n after ");
+        assert!(c.text == "before after");
+        assert!(c.eval() == Some(PgnEval::Mate(-3)));
+        assert!(c.clock() == Some(3723.5));
+        let c = Comment::parse("[%eval 1.5,22]");
+        assert!(c.eval() == Some(PgnEval::Pawns(1.5)) && c.text.is_empty());
+        assert!(c.clock().is_none());
+    }
+
+    #[test]
+    fn malformed_games() {
+        let kind = |pgn: &str| parse_pgn(pgn).remove(0).unwrap_err().kind;
+        assert!(kind("[Event \"x]\n\n1. e4 *") == PgnErrorKind::BadTag);
+        assert!(kind("1. e4 (1. d4 *") == PgnErrorKind::UnexpectedEnd);
+        assert!(kind("1. e4 {open *") == PgnErrorKind::UnexpectedEnd);
+        assert!(kind("1. e4 ) *") == PgnErrorKind::UnexpectedToken(")".to_string()));
+        assert!(kind("1. e4 * e5") == PgnErrorKind::UnexpectedToken("e5".to_string()));
+        assert!(kind("1 e4 *") == PgnErrorKind::UnexpectedToken("1".to_string()));
+        assert!(kind("$1 1. e4 *") == PgnErrorKind::UnexpectedToken("$1".to_string()));
+        assert!(matches!(
+            kind("[FEN \"bad\"]\n\n*"),
+            PgnErrorKind::BadFen(_)
+        ));
+        // Without a result token the tag is used
+        let game = parse_pgn("[Result \"1/2-1/2\"]\n\n1. e4 e5")
+            .remove(0)
+            .unwrap();
+        assert!(game.result == PgnResult::Draw && game.moves().len() == 2);
+    }
+
+    #[test]
+    fn comment_spanning_lines_with_brackets() {
+        let pgn = "[Event \"a\"]\n\n1. e4 {a comment\n[%clk 0:01:00]\nstill} e5 *\n[Event \"b\"]\n\n1. d4 *\n";
+        let games = parse_pgn(pgn);
+        assert!(games.len() == 2);
+        let game = games[0].clone().unwrap();
+        assert!(game.mainline.moves[0].comments[0].text == "a comment still");
+        assert!(game.mainline.moves[0].comments[0].clock() == Some(60.0));
+        assert!(games[1].clone().unwrap().tag("Event") == Some("b"));
+    }
+
+    #[test]
+    fn unclosed_comment_ends_at_the_next_game() {
+        let pgn = "[Event \"a\"]\n\n1. e4 {never closed *\n\n[Event \"b\"]\n\n1. d4 *\n";
+        let games = parse_pgn(pgn);
+        assert!(games.len() == 2);
+        assert!(games[0].clone().unwrap_err().kind == PgnErrorKind::UnexpectedEnd);
+        assert!(games[1].clone().unwrap().tag("Event") == Some("b"));
+    }
+
+    #[test]
+    fn writes_export_format() {
+        let game = parse_pgn(GAMES).remove(0).unwrap();
+        let expected = r#"[Event "Casual"]
+[Site "?"]
+[Date "2024.01.02"]
+[Round "-"]
+[White "A \"Quoted\" Player"]
+[Black "B"]
+[Result "1-0"]
+
+{Opening comment} 1. e4 e5 2. Nf3 {[%clk 0:03:00] [%eval 0.25] Main move} 2...
+Nc6 (2... d6 $6 3. d4 (3. Bc4 Be7) 3... exd4) 3. Bb5 $1 a6 $6 4. Ba4 Nf6 5. O-O
+{rest of line comment} 5... Be7 1-0
+"#;
+        assert!(game.to_pgn() == expected);
+    }
+
+    #[test]
+    fn fills_the_seven_tag_roster() {
+        let mut game = PgnGame::new(parse_fen(START_FEN.to_string()).unwrap());
+        game.set_tag("Annotator", "me");
+        game.set_tag("White", "W");
+        game.set_tag("White", "X");
+        let pgn = game.to_pgn();
+        let expected = "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+            [White \"X\"]\n[Black \"?\"]\n[Result \"*\"]\n[Annotator \"me\"]\n\n*\n";
+        assert!(pgn == expected);
+
+        let setup = parse_pgn(GAMES).remove(2).unwrap();
+        let pgn = setup.to_pgn();
+        assert!(pgn.contains(
+            "[Result \"0-1\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n"
+        ));
+        assert!(pgn.ends_with("\n\n1. e4 Kd7 2. Kf2 0-1\n"));
+    }
+
+    #[test]
+    fn wraps_long_movetext() {
+        let mut board = parse_fen(START_FEN.to_string()).unwrap();
+        let mut game = PgnGame::new(board.clone());
+        // Knights back and forth
+        for san in ["Nf3", "Nf6", "Ng1", "Ng8"].iter().cycle().take(60) {
+            let m = board.parse_san(san).unwrap();
+            board.make_move(m);
+            game.mainline.moves.push(PgnMove::new(m));
+        }
+        let pgn = game.to_pgn();
+        assert!(pgn.lines().all(|line| line.len() <= 80));
+        assert!(pgn.lines().filter(|line| line.starts_with("1. ")).count() == 1);
+        assert!(parse_pgn(&pgn).remove(0).unwrap().mainline == game.mainline);
+    }
+
+    #[test]
+    fn read_and_write_is_stable() {
+        let games: Vec<PgnGame> = parse_pgn(GAMES)
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect();
+        let written = write_pgn(&games);
+        let reread: Vec<PgnGame> = parse_pgn(&written)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert!(reread.len() == games.len());
+        for (a, b) in games.iter().zip(&reread) {
+            assert!(a.start == b.start && a.mainline == b.mainline && a.result == b.result);
+        }
+        assert!(write_pgn(&reread) == written);
+    }
+}
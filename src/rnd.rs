@@ -6,7 +6,7 @@ pub trait RndGen<T> {
 
 type State = [u64; 4];
 
-struct Xoshiro256p {
+pub struct Xoshiro256p {
     s: State,
 }
 
@@ -39,7 +39,7 @@ fn xoshiro256p(state: &mut State) -> u64 {
 
 #[inline]
 fn rol64(x: u64, k: u32) -> u64 {
-    (x << k) | (x >> (64 - k))
+    x.rotate_left(k)
 }
 
 #[inline]
@@ -0,0 +1,419 @@
+use crate::api::{Board, BoardSide, Move, MoveType, Piece, PromotionType, Square};
+use crate::bitboard::BbBoardState;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SanErrorKind {
+    Empty,
+    // Not shaped like a SAN move
+    Syntax,
+    // No legal move fits
+    Illegal,
+    // More than one legal move fits
+    Ambiguous,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SanError {
+    pub san: String,
+    pub kind: SanErrorKind,
+}
+
+impl std::fmt::Display for SanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            SanErrorKind::Empty => write!(f, "empty move"),
+            SanErrorKind::Syntax => write!(f, "'{}' is not a SAN move", self.san),
+            SanErrorKind::Illegal => write!(f, "'{}' is not a legal move", self.san),
+            SanErrorKind::Ambiguous => write!(f, "'{}' could be more than one move", self.san),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+// What a SAN string says about a move, before matching it against the legal moves
+#[derive(Debug, PartialEq)]
+enum SanPattern {
+    Castle(BoardSide),
+    Move {
+        piece: Piece,
+        from_file: Option<u8>,
+        from_rank: Option<u8>,
+        to: Square,
+        promotion: Option<PromotionType>,
+        // Written with an `x`
+        capture: bool,
+    },
+}
+
+fn is_capture(m: Move) -> bool {
+    matches!(
+        m.kind(),
+        MoveType::Capture | MoveType::EnPassant | MoveType::PromoteCapture(_)
+    )
+}
+
+fn piece_from_char(c: char) -> Option<Piece> {
+    match c {
+        'N' => Some(Piece::N),
+        'B' => Some(Piece::B),
+        'R' => Some(Piece::R),
+        'Q' => Some(Piece::Q),
+        'K' => Some(Piece::K),
+        _ => None,
+    }
+}
+
+fn promotion_from_char(c: char) -> Option<PromotionType> {
+    match c {
+        'N' => Some(PromotionType::Knight),
+        'B' => Some(PromotionType::Bishop),
+        'R' => Some(PromotionType::Rook),
+        'Q' => Some(PromotionType::Queen),
+        _ => None,
+    }
+}
+
+fn piece_letter(piece: Piece) -> &'static str {
+    match piece {
+        Piece::P => "",
+        Piece::N => "N",
+        Piece::B => "B",
+        Piece::R => "R",
+        Piece::Q => "Q",
+        Piece::K => "K",
+    }
+}
+
+fn promotion_letter(p: PromotionType) -> char {
+    match p {
+        PromotionType::Knight => 'N',
+        PromotionType::Bishop => 'B',
+        PromotionType::Rook => 'R',
+        PromotionType::Queen => 'Q',
+    }
+}
+
+// Check and mate marks, annotations like `!?` and an `e.p.` suffix carry nothing
+// needed to find the move, so they are dropped
+fn strip_suffixes(s: &str) -> &str {
+    let s = s.trim();
+    let s = s.strip_suffix("e.p.").unwrap_or(s).trim_end();
+    s.trim_end_matches(['+', '#', '!', '?'])
+}
+
+fn parse_pattern(s: &str) -> Option<SanPattern> {
+    match s {
+        "O-O" | "0-0" => return Some(SanPattern::Castle(BoardSide::KingSide)),
+        "O-O-O" | "0-0-0" => return Some(SanPattern::Castle(BoardSide::QueenSide)),
+        _ => (),
+    }
+    let mut chars: Vec<char> = s.chars().collect();
+
+    let piece = match chars.first().copied().and_then(piece_from_char) {
+        Some(piece) => {
+            chars.remove(0);
+            piece
+        }
+        None => Piece::P,
+    };
+
+    // `e8=Q`, or `e8Q` as some programs write it
+    let promotion = match chars.last().copied().filter(char::is_ascii_uppercase) {
+        Some(c) if piece == Piece::P => {
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+            Some(promotion_from_char(c)?)
+        }
+        Some(_) => return None,
+        None => None,
+    };
+
+    if chars.len() < 2 {
+        return None;
+    }
+    let rank = chars.pop()?;
+    let file = chars.pop()?;
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    let to = Square::new((rank as u8 - b'1') * 8 + (file as u8 - b'a'));
+
+    // Whatever is left is disambiguation and the capture mark, in that order
+    let mut rest = chars.into_iter().peekable();
+    let from_file = rest
+        .next_if(|c| ('a'..='h').contains(c))
+        .map(|c| c as u8 - b'a');
+    let from_rank = rest
+        .next_if(|c| ('1'..='8').contains(c))
+        .map(|c| c as u8 - b'1');
+    let capture = rest.next_if_eq(&'x').is_some();
+    if rest.next().is_some() {
+        return None;
+    }
+
+    Some(SanPattern::Move {
+        piece,
+        from_file,
+        from_rank,
+        to,
+        promotion,
+        capture,
+    })
+}
+
+impl BbBoardState {
+    // The legal move a SAN string describes. Check marks and annotations are
+    // not verified.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let error = |kind| SanError {
+            san: san.to_string(),
+            kind,
+        };
+        let s = strip_suffixes(san);
+        if s.is_empty() {
+            return Err(error(SanErrorKind::Empty));
+        }
+        let pattern = parse_pattern(s).ok_or_else(|| error(SanErrorKind::Syntax))?;
+
+        let mut matches = self.legal_moves().into_iter().filter(|m| match pattern {
+            SanPattern::Castle(wing) => m.castling_side() == Some(wing),
+            SanPattern::Move {
+                piece,
+                from_file,
+                from_rank,
+                to,
+                promotion,
+                capture,
+            } => {
+                let promoted = match m.kind() {
+                    MoveType::Promote(p) | MoveType::PromoteCapture(p) => Some(p),
+                    _ => None,
+                };
+                // A pawn named by its destination alone moves straight ahead
+                let from_file = match (piece, from_file) {
+                    (Piece::P, None) => Some(to.file_index()),
+                    _ => from_file,
+                };
+                m.castling_side().is_none()
+                    && m.to() == to
+                    && self.pieces.piece_at(m.from()).map(|(_, p)| p) == Some(piece)
+                    && from_file.is_none_or(|f| f == m.from().file_index())
+                    && from_rank.is_none_or(|r| r == m.from().rank_index())
+                    && promoted == promotion
+                    && (!capture || is_capture(*m))
+            }
+        });
+        match (matches.next(), matches.next()) {
+            (Some(m), None) => Ok(m),
+            (Some(_), Some(_)) => Err(error(SanErrorKind::Ambiguous)),
+            (None, _) => Err(error(SanErrorKind::Illegal)),
+        }
+    }
+
+    // SAN for a legal move, with the least disambiguation that makes it unique
+    // and a `+` or `#` suffix
+    pub fn to_san(&self, m: Move) -> String {
+        let mut san = match m.castling_side() {
+            Some(BoardSide::KingSide) => "O-O".to_string(),
+            Some(BoardSide::QueenSide) => "O-O-O".to_string(),
+            None => self.san_body(m),
+        };
+
+        let mut after = self.clone();
+        after.make_move(m);
+        if after.in_check() {
+            san.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+
+    fn san_body(&self, m: Move) -> String {
+        let piece = self.pieces.piece_at(m.from()).map_or(Piece::P, |(_, p)| p);
+        let capture = is_capture(m);
+        let mut san = piece_letter(piece).to_string();
+
+        if piece == Piece::P {
+            if capture {
+                san.push((b'a' + m.from().file_index()) as char);
+            }
+        } else {
+            let rivals: Vec<Square> = self
+                .legal_moves()
+                .into_iter()
+                .filter(|o| {
+                    o.to() == m.to()
+                        && o.from() != m.from()
+                        && o.castling_side().is_none()
+                        && self.pieces.piece_at(o.from()).map(|(_, p)| p) == Some(piece)
+                })
+                .map(|o| o.from())
+                .collect();
+            if !rivals.is_empty() {
+                let file = m.from().file_index();
+                let rank = m.from().rank_index();
+                if rivals.iter().all(|s| s.file_index() != file) {
+                    san.push((b'a' + file) as char);
+                } else if rivals.iter().all(|s| s.rank_index() != rank) {
+                    san.push((b'1' + rank) as char);
+                } else {
+                    san.push_str(&m.from().to_string());
+                }
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&m.to().to_string());
+        if let MoveType::Promote(p) | MoveType::PromoteCapture(p) = m.kind() {
+            san.push('=');
+            san.push(promotion_letter(p));
+        }
+        san
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+    use crate::san::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    fn board(fen: &str) -> BbBoardState {
+        parse_fen(fen.to_string()).unwrap()
+    }
+
+    fn play(board: &mut BbBoardState, moves: &[&str]) {
+        for san in moves {
+            let m = board.parse_san(san).unwrap();
+            board.make_move(m);
+        }
+    }
+
+    #[test]
+    fn parse_simple_moves() {
+        let start = board(START);
+        let e4 = start.parse_san("e4").unwrap();
+        assert!(e4.from() == Square::new(12) && e4.to() == Square::new(28));
+        assert!(e4.kind() == MoveType::DoublePush);
+        let nf3 = start.parse_san("Nf3").unwrap();
+        assert!(nf3.from() == Square::new(6) && nf3.to() == Square::new(21));
+        assert!(start.parse_san("Ng1f3").unwrap() == nf3);
+        assert!(start.parse_san("Nf3!?").unwrap() == nf3);
+    }
+
+    #[test]
+    fn parse_disambiguation() {
+        // Knights on b1 and f1 can both reach d2
+        let b = board("4k3/8/8/8/8/8/8/1N3N1K w - - 0 1");
+        assert!(b.parse_san("Nd2").unwrap_err().kind == SanErrorKind::Ambiguous);
+        assert!(b.parse_san("Nbd2").unwrap().from() == Square::new(1));
+        assert!(b.parse_san("Nfd2").unwrap().from() == Square::new(5));
+        // Rooks on a1 and a5
+        let b = board("4k3/8/8/R7/8/8/8/R6K w - - 0 1");
+        assert!(b.parse_san("Ra3").unwrap_err().kind == SanErrorKind::Ambiguous);
+        assert!(b.parse_san("R1a3").unwrap().from() == Square::new(0));
+        assert!(b.parse_san("R5a3").unwrap().from() == Square::new(32));
+    }
+
+    #[test]
+    fn parse_special_moves() {
+        let b = board("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+        let m = b.parse_san("exd6 e.p.").unwrap();
+        assert!(m.kind() == MoveType::EnPassant);
+        assert!(b.parse_san("exd6").unwrap() == m);
+        // Only a push can leave out the pawn's file
+        assert!(b.parse_san("d6").unwrap_err().kind == SanErrorKind::Illegal);
+
+        let b = board("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1");
+        assert!(b.parse_san("O-O-O").unwrap().kind() == MoveType::CastleQueen);
+        assert!(b.parse_san("0-0").unwrap().kind() == MoveType::CastleKing);
+
+        let b = board("k7/4P3/8/8/8/8/8/4K3 w - - 0 1");
+        let m = b.parse_san("e8=Q+").unwrap();
+        assert!(m.kind() == MoveType::Promote(PromotionType::Queen));
+        let m = b.parse_san("e8N").unwrap();
+        assert!(m.kind() == MoveType::Promote(PromotionType::Knight));
+        assert!(b.parse_san("e8").unwrap_err().kind == SanErrorKind::Illegal);
+        assert!(b.parse_san("e8=K").unwrap_err().kind == SanErrorKind::Syntax);
+
+        let mut b = board(START);
+        play(&mut b, &["f3", "e5", "g4"]);
+        let m = b.parse_san("Qh4#").unwrap();
+        assert!(m.from() == Square::new(59) && m.to() == Square::new(31));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let start = board(START);
+        let kind = |s: &str| start.parse_san(s).unwrap_err().kind;
+        assert!(kind("") == SanErrorKind::Empty);
+        assert!(kind("+") == SanErrorKind::Empty);
+        assert!(kind("Ke2") == SanErrorKind::Illegal);
+        assert!(kind("e5") == SanErrorKind::Illegal);
+        assert!(kind("O-O") == SanErrorKind::Illegal);
+        assert!(kind("Zf3") == SanErrorKind::Syntax);
+        assert!(kind("Nf9") == SanErrorKind::Syntax);
+        assert!(kind("Nf3x") == SanErrorKind::Syntax);
+        assert!(kind("Nf3=Q") == SanErrorKind::Syntax);
+        // Marked as a capture with nothing to take
+        assert!(kind("Nxf3") == SanErrorKind::Illegal);
+        assert!(kind("exe4") == SanErrorKind::Illegal);
+        let e = start.parse_san("Ke2").unwrap_err();
+        assert!(e.to_string() == "'Ke2' is not a legal move");
+    }
+
+    #[test]
+    fn generate_san() {
+        let start = board(START);
+        let m = start.parse_san("Nf3").unwrap();
+        assert!(start.to_san(m) == "Nf3");
+
+        let b = board("4k3/8/8/8/8/8/8/1N3N1K w - - 0 1");
+        assert!(b.to_san(b.parse_san("Nbd2").unwrap()) == "Nbd2");
+        let b = board("4k3/8/8/R7/8/8/8/R6K w - - 0 1");
+        assert!(b.to_san(b.parse_san("R1a3").unwrap()) == "R1a3");
+        // Queens on h4, e4 and h1 all reach e1, so h4 needs file and rank
+        let b = board("2k5/8/8/8/4Q2Q/8/8/K6Q w - - 0 1");
+        assert!(b.to_san(b.parse_san("Qh4e1").unwrap()) == "Qh4e1");
+        assert!(b.to_san(b.parse_san("Qee1").unwrap()) == "Qee1");
+
+        let b = board("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+        assert!(b.to_san(b.parse_san("exd6").unwrap()) == "exd6");
+        let b = board("k7/4P3/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(b.to_san(b.parse_san("e8=Q").unwrap()) == "e8=Q+");
+        let b = board("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert!(b.to_san(b.parse_san("O-O-O").unwrap()) == "O-O-O");
+
+        let mut b = board(START);
+        play(&mut b, &["f3", "e5", "g4"]);
+        assert!(b.to_san(b.parse_san("Qh4").unwrap()) == "Qh4#");
+    }
+
+    #[test]
+    fn every_legal_move_round_trips() {
+        let fens = [
+            KIWIPETE,
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        ];
+        for fen in fens {
+            let b = board(fen);
+            for m in b.legal_moves() {
+                let san = b.to_san(m);
+                assert!(b.parse_san(&san) == Ok(m), "{} {}", fen, san);
+            }
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::api::{Board, Move, MoveType, Piece, Side};
+use crate::bitboard::{BbBoardState, PIECES};
+
+// Kept back from the clock for communication and move overhead
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+
+const MAX_DEPTH: u32 = 64;
+
+// Score of mate at the root, less one for each ply it takes
+const MATE: i32 = 30000;
+const MATE_BOUND: i32 = MATE - 2 * MAX_DEPTH as i32 - 64;
+
+// How often the clock and the stop flag are looked at
+const CHECK_EVERY: u64 = 1024;
+
+// What a search may use. Limits left unset don't apply.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // Clock and increment of each side, indexed by `Side as usize`
+    pub time: [Option<Duration>; 2],
+    pub inc: [Duration; 2],
+    // Moves until the next time control, or the rest of the game when unset
+    pub movestogo: Option<u32>,
+    // Keep going until stopped, and don't report a move before then
+    pub infinite: bool,
+    // Search on the opponent's time. The clock only starts once pondering is
+    // cleared, and until then the move is held back as if infinite.
+    pub ponder: bool,
+}
+
+impl Limits {
+    // Time `side` should spend on this move
+    pub fn budget(&self, side: Side) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+        let clock = self.time[side as usize].map(|time| {
+            let left = time.saturating_sub(MOVE_OVERHEAD);
+            let share =
+                left / self.movestogo.unwrap_or(30).max(1) + self.inc[side as usize] * 3 / 4;
+            share.min(left)
+        });
+        match (self.movetime, clock) {
+            (Some(movetime), Some(clock)) => Some(movetime.min(clock)),
+            (movetime, clock) => movetime.or(clock),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Score {
+    Cp(i32),
+    // Moves to mate, negative when the side to move is getting mated
+    Mate(i32),
+}
+
+impl Score {
+    fn from_internal(score: i32) -> Score {
+        if score > MATE_BOUND {
+            Score::Mate((MATE - score + 1) / 2)
+        } else if score < -MATE_BOUND {
+            Score::Mate(-(MATE + score) / 2)
+        } else {
+            Score::Cp(score)
+        }
+    }
+}
+
+// Progress of a search, reported after each iteration
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub score: Score,
+    // Searched so far, this iteration included
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    // None only when there are no legal moves
+    pub best: Option<Move>,
+    pub score: Score,
+    pub pv: Vec<Move>,
+    // One for each completed iteration
+    pub iterations: Vec<SearchInfo>,
+}
+
+// Iterative deepening negamax with alpha-beta pruning and a capture search at
+// the leaves. `history` holds the hashes of the positions before this one, for
+// repetitions. The first iteration always completes so there is a move to play;
+// after that `stop`, the node limit or the clock can end the search, and the
+// unfinished iteration is thrown away. The clock doesn't run while `pondering`
+// is set.
+pub fn search(
+    board: &BbBoardState,
+    history: &[u64],
+    limits: &Limits,
+    stop: &AtomicBool,
+    pondering: &AtomicBool,
+    report: &mut dyn FnMut(&SearchInfo),
+) -> SearchResult {
+    let start = Instant::now();
+    let mut searcher = Searcher {
+        board: board.clone(),
+        path: history.to_vec(),
+        stop,
+        pondering,
+        budget: limits.budget(board.to_move),
+        deadline: None,
+        node_limit: limits.nodes,
+        nodes: 0,
+        stopped: false,
+        can_stop: false,
+    };
+    let mut result = SearchResult {
+        best: None,
+        score: Score::Cp(0),
+        pv: Vec::new(),
+        iterations: Vec::new(),
+    };
+
+    searcher.start_clock();
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+    for depth in 1..=max_depth {
+        let mut pv = Vec::new();
+        let score = searcher.negamax(depth, 0, -MATE, MATE, &result.pv, &mut pv);
+        if searcher.stopped {
+            break;
+        }
+        searcher.can_stop = true;
+        result.best = pv.first().copied();
+        result.score = Score::from_internal(score);
+        result.pv = pv;
+        let info = SearchInfo {
+            depth,
+            score: result.score,
+            nodes: searcher.nodes,
+            time: start.elapsed(),
+            pv: result.pv.clone(),
+        };
+        report(&info);
+        result.iterations.push(info);
+
+        // No move to search, or another iteration wouldn't finish in time
+        searcher.start_clock();
+        let late = |(b, d): (Duration, Instant)| Instant::now() + b / 2 > d;
+        if result.best.is_none() || searcher.budget.zip(searcher.deadline).is_some_and(late) {
+            break;
+        }
+    }
+    result
+}
+
+struct Searcher<'a> {
+    board: BbBoardState,
+    // Hashes of the positions before the current one
+    path: Vec<u64>,
+    stop: &'a AtomicBool,
+    pondering: &'a AtomicBool,
+    // Time for the move, which runs out at `deadline` once the clock starts
+    budget: Option<Duration>,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+    nodes: u64,
+    stopped: bool,
+    // Nothing stops the search until an iteration has finished
+    can_stop: bool,
+}
+
+impl Searcher<'_> {
+    // Start counting down the budget, unless it already is or we're pondering
+    fn start_clock(&mut self) {
+        if self.deadline.is_none() && !self.pondering.load(Ordering::Relaxed) {
+            self.deadline = self.budget.map(|b| Instant::now() + b);
+        }
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.can_stop && !self.stopped && self.nodes.is_multiple_of(CHECK_EVERY) {
+            self.start_clock();
+            self.stopped = self.stop.load(Ordering::Relaxed)
+                || self.deadline.is_some_and(|d| Instant::now() >= d);
+        }
+        if self.can_stop && self.node_limit.is_some_and(|n| self.nodes >= n) {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    // The position has come up before since the last capture or pawn move
+    fn is_repetition(&self) -> bool {
+        let hash = self.board.hash();
+        let window = self.board.halfmove_clock() as usize;
+        self.path
+            .iter()
+            .rev()
+            .take(window)
+            .skip(1)
+            .step_by(2)
+            .any(|&h| h == hash)
+    }
+
+    fn negamax(
+        &mut self,
+        depth: u32,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+        pv_hint: &[Move],
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+        // Mate comes first, even on the move that reaches the fifty-move limit
+        let mut moves = self.board.legal_moves();
+        if moves.is_empty() {
+            return match self.board.in_check() {
+                true => -MATE + ply as i32,
+                false => 0,
+            };
+        }
+        if ply > 0
+            && (self.is_repetition()
+                || self.board.halfmove_clock() >= 100
+                || self.board.pieces.is_insufficient_material())
+        {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(ply, alpha, beta);
+        }
+        order_moves(&self.board, &mut moves, pv_hint.first().copied());
+
+        let mut child_pv = Vec::new();
+        for m in moves {
+            let hint = match pv_hint.first() {
+                Some(&first) if first == m => &pv_hint[1..],
+                _ => &[],
+            };
+            self.path.push(self.board.hash());
+            let undo = self.board.make_move(m);
+            child_pv.clear();
+            let score = -self.negamax(depth - 1, ply + 1, -beta, -alpha, hint, &mut child_pv);
+            self.board.unmake_move(m, undo);
+            self.path.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(m);
+                pv.extend_from_slice(&child_pv);
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        alpha
+    }
+
+    // Only captures and promotions, unless in check, until the position is quiet
+    fn quiescence(&mut self, ply: u32, mut alpha: i32, beta: i32) -> i32 {
+        let in_check = self.board.in_check();
+        if !in_check {
+            let stand_pat = evaluate(&self.board);
+            if stand_pat >= beta || ply >= 2 * MAX_DEPTH {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+        }
+
+        let mut moves = self.board.legal_moves();
+        if moves.is_empty() {
+            return match in_check {
+                true => -MATE + ply as i32,
+                false => 0,
+            };
+        }
+        if !in_check {
+            moves.retain(|m| is_tactical(*m));
+        }
+        order_moves(&self.board, &mut moves, None);
+        for m in moves {
+            self.nodes += 1;
+            if self.should_stop() {
+                return 0;
+            }
+            let undo = self.board.make_move(m);
+            let score = -self.quiescence(ply + 1, -beta, -alpha);
+            self.board.unmake_move(m, undo);
+            if score > alpha {
+                alpha = score;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        alpha
+    }
+}
+
+fn is_tactical(m: Move) -> bool {
+    matches!(
+        m.kind(),
+        MoveType::Capture
+            | MoveType::EnPassant
+            | MoveType::Promote(_)
+            | MoveType::PromoteCapture(_)
+    )
+}
+
+fn piece_value(piece: Piece) -> i32 {
+    match piece {
+        Piece::P => 100,
+        Piece::N => 320,
+        Piece::B => 330,
+        Piece::R => 500,
+        Piece::Q => 900,
+        Piece::K => 0,
+    }
+}
+
+// The previous iteration's move first, then captures of the most valuable piece
+// by the least valuable one, then the rest
+fn order_moves(board: &BbBoardState, moves: &mut [Move], first: Option<Move>) {
+    let value = |s| board.pieces.piece_at(s).map_or(0, |(_, p)| piece_value(p));
+    moves.sort_by_cached_key(|&m| {
+        if Some(m) == first {
+            return i32::MIN;
+        }
+        let gain = match m.kind() {
+            MoveType::Capture | MoveType::PromoteCapture(_) => 10 * value(m.to()) - value(m.from()),
+            MoveType::EnPassant => 9 * piece_value(Piece::P),
+            MoveType::Promote(p) => piece_value(p.piece()),
+            _ => 0,
+        };
+        -gain
+    });
+}
+
+// Bonus for a piece of `side` on the square: pawns for advancing, knights and
+// bishops for being near the centre
+fn placement(piece: Piece, side: Side, rank: u8, file: u8) -> i32 {
+    let advanced = match side {
+        Side::White => rank,
+        Side::Black => 7 - rank,
+    } as i32;
+    let centre = ((2 * rank as i32 - 7).abs()).max((2 * file as i32 - 7).abs()) / 2;
+    match piece {
+        Piece::P => 5 * advanced,
+        Piece::N | Piece::B => 5 * (3 - centre),
+        _ => 0,
+    }
+}
+
+// Static score for the side to move
+pub fn evaluate(board: &BbBoardState) -> i32 {
+    let mut score = 0;
+    for side in [Side::White, Side::Black] {
+        let sign = if side == board.to_move { 1 } else { -1 };
+        for piece in PIECES {
+            for s in board.pieces.get(side, piece) {
+                let value =
+                    piece_value(piece) + placement(piece, side, s.rank_index(), s.file_index());
+                score += sign * value;
+            }
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+    use crate::search::*;
+
+    fn run(fen: &str, limits: Limits) -> SearchResult {
+        let board = parse_fen(fen.to_string()).unwrap();
+        let no = AtomicBool::new(false);
+        search(&board, &[], &limits, &no, &no, &mut |_| ())
+    }
+
+    fn depth(d: u32) -> Limits {
+        Limits {
+            depth: Some(d),
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn time_budget() {
+        let mut limits = Limits::default();
+        assert!(limits.budget(Side::White).is_none());
+        limits.time = [Some(Duration::from_secs(60)), Some(Duration::from_secs(1))];
+        limits.inc = [Duration::from_secs(2), Duration::ZERO];
+        limits.movestogo = Some(10);
+        let white = limits.budget(Side::White).unwrap();
+        assert!(white > Duration::from_secs(7) && white < Duration::from_secs(8));
+        assert!(limits.budget(Side::Black).unwrap() < Duration::from_secs(1));
+        limits.movetime = Some(Duration::from_secs(1));
+        assert!(limits.budget(Side::White) == Some(Duration::from_secs(1)));
+        limits.infinite = true;
+        assert!(limits.budget(Side::White).is_none());
+    }
+
+    #[test]
+    fn finds_mates() {
+        let r = run("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", depth(2));
+        assert!(r.score == Score::Mate(1));
+        assert!(r.best.unwrap().to_string() == "a1a8");
+        // Still mate on the hundredth move without a capture or pawn move
+        let r = run("6k1/5ppp/8/8/8/8/8/R5K1 w - - 99 80", depth(2));
+        assert!(r.score == Score::Mate(1));
+
+        // Kg8 is forced and Ra8 mates
+        let r = run("7k/8/6K1/8/8/8/8/R7 b - - 0 1", depth(3));
+        assert!(r.score == Score::Mate(-1) && r.pv.len() == 2);
+    }
+
+    #[test]
+    fn wins_material_and_sees_recaptures() {
+        // The hanging queen
+        let r = run("4k3/8/8/7q/8/8/8/4K2R b - - 0 1", depth(3));
+        assert!(r.best.unwrap().to_string() == "h5h1");
+        // Taking the defended pawn loses the queen
+        let r = run("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", depth(2));
+        assert!(r.best.unwrap().to_string() != "d1d5");
+    }
+
+    #[test]
+    fn iterations_and_limits() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let r = run(fen, depth(3));
+        assert!(r.iterations.len() == 3);
+        for (i, info) in r.iterations.iter().enumerate() {
+            assert!(info.depth == i as u32 + 1 && !info.pv.is_empty());
+        }
+        assert!(r.iterations[0].nodes < r.iterations[2].nodes);
+        assert!(r.best == Some(r.pv[0]) && r.iterations[2].pv == r.pv);
+
+        // The first iteration always completes
+        let r = run(
+            fen,
+            Limits {
+                nodes: Some(1),
+                ..Limits::default()
+            },
+        );
+        assert!(r.iterations.len() == 1 && r.best.is_some());
+        let board = parse_fen(fen.to_string()).unwrap();
+        let (yes, no) = (AtomicBool::new(true), AtomicBool::new(false));
+        let r = search(&board, &[], &depth(10), &yes, &no, &mut |_| ());
+        assert!(r.iterations.len() == 1 && r.best.is_some());
+
+        // The clock waits for pondering to end
+        let limits = Limits {
+            movetime: Some(Duration::ZERO),
+            ponder: true,
+            ..depth(3)
+        };
+        let r = search(&board, &[], &limits, &no, &yes, &mut |_| ());
+        assert!(r.iterations.len() == 3);
+        let r = search(&board, &[], &limits, &no, &no, &mut |_| ());
+        assert!(r.iterations.len() == 1);
+    }
+
+    #[test]
+    fn draws_and_no_moves() {
+        let r = run("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", depth(3));
+        assert!(r.best.is_none() && r.score == Score::Cp(0));
+
+        // A queen down, repeating a position is the best white can do
+        let fen = "4k3/8/8/8/8/8/q7/4K1N1 w - - 10 30";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let nf3 = board.parse_san("Nf3").unwrap();
+        let undo = board.make_move(nf3);
+        let repeated = board.hash();
+        board.unmake_move(nf3, undo);
+        let no = AtomicBool::new(false);
+        let r = search(&board, &[repeated], &depth(3), &no, &no, &mut |_| ());
+        assert!(r.best == Some(nf3) && r.score == Score::Cp(0));
+        let r = search(&board, &[], &depth(3), &no, &no, &mut |_| ());
+        assert!(matches!(r.score, Score::Cp(cp) if cp < -500));
+    }
+}
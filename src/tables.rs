@@ -0,0 +1,176 @@
+use crate::api::{Direction, Square};
+use crate::bitboard::Bitboard;
+
+// Lookup tables indexed by `Square::v`, built at compile time
+
+// (rank, file) steps
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (2, 1),
+    (1, 2),
+    (-1, 2),
+    (-2, 1),
+    (-2, -1),
+    (-1, -2),
+    (1, -2),
+    (2, -1),
+];
+
+pub static KNIGHT_ATTACKS: [Bitboard; 64] = leaper_table(&KNIGHT_STEPS);
+
+pub static KING_ATTACKS: [Bitboard; 64] = direction_table(&Direction::COMPASS);
+
+// Indexed by `Side as usize`, then square
+pub static PAWN_ATTACKS: [[Bitboard; 64]; 2] = [
+    direction_table(&[Direction::NW, Direction::NE]),
+    direction_table(&[Direction::SW, Direction::SE]),
+];
+
+// Squares strictly between two squares on a shared rank, file or diagonal
+pub static BETWEEN: [[Bitboard; 64]; 64] = between_table();
+
+// The whole rank, file or diagonal through two squares, including both
+pub static LINE: [[Bitboard; 64]; 64] = line_table();
+
+// Square reached by a (rank, file) step, if it stays on the board
+const fn step(s: u8, dr: i8, df: i8) -> Option<u8> {
+    let r = (s / 8) as i8 + dr;
+    let f = (s % 8) as i8 + df;
+    if r >= 0 && r < 8 && f >= 0 && f < 8 {
+        Some((r * 8 + f) as u8)
+    } else {
+        None
+    }
+}
+
+// Unit step leading from `a` towards `b`, if both share a rank, file or diagonal
+const fn direction(a: u8, b: u8) -> Option<(i8, i8)> {
+    let dr = (b / 8) as i8 - (a / 8) as i8;
+    let df = (b % 8) as i8 - (a % 8) as i8;
+    if a == b || (dr != 0 && df != 0 && dr.abs() != df.abs()) {
+        None
+    } else {
+        Some((dr.signum(), df.signum()))
+    }
+}
+
+// Squares from `s` (exclusive) in one direction up to `stop` (exclusive) or the edge
+const fn ray(s: u8, dr: i8, df: i8, stop: Option<u8>) -> u64 {
+    let mut bb = 0;
+    let mut cur = s;
+    while let Some(t) = step(cur, dr, df) {
+        if let Some(stop) = stop {
+            if t == stop {
+                break;
+            }
+        }
+        bb |= 1 << t;
+        cur = t;
+    }
+    bb
+}
+
+const fn leaper_table(steps: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [Bitboard::empty(); 64];
+    let mut s = 0;
+    while s < 64 {
+        let coord = Bitboard::get_coord(Square::new(s as u8));
+        let mut i = 0;
+        while i < steps.len() {
+            let (dr, df) = steps[i];
+            table[s].v |= coord.shift_v(dr).shift_h(df).v;
+            i += 1;
+        }
+        s += 1;
+    }
+    table
+}
+
+const fn direction_table(directions: &[Direction]) -> [Bitboard; 64] {
+    let mut table = [Bitboard::empty(); 64];
+    let mut s = 0;
+    while s < 64 {
+        let coord = Bitboard::get_coord(Square::new(s as u8));
+        let mut i = 0;
+        while i < directions.len() {
+            table[s].v |= coord.shift(directions[i]).v;
+            i += 1;
+        }
+        s += 1;
+    }
+    table
+}
+
+const fn between_table() -> [[Bitboard; 64]; 64] {
+    let mut table = [[Bitboard::empty(); 64]; 64];
+    let mut a = 0;
+    while a < 64 {
+        let mut b = 0;
+        while b < 64 {
+            if let Some((dr, df)) = direction(a as u8, b as u8) {
+                table[a][b] = Bitboard::new(ray(a as u8, dr, df, Some(b as u8)));
+            }
+            b += 1;
+        }
+        a += 1;
+    }
+    table
+}
+
+const fn line_table() -> [[Bitboard; 64]; 64] {
+    let mut table = [[Bitboard::empty(); 64]; 64];
+    let mut a = 0;
+    while a < 64 {
+        let mut b = 0;
+        while b < 64 {
+            if let Some((dr, df)) = direction(a as u8, b as u8) {
+                let v = ray(a as u8, dr, df, None) | ray(a as u8, -dr, -df, None) | 1 << a;
+                table[a][b] = Bitboard::new(v);
+            }
+            b += 1;
+        }
+        a += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tables::*;
+
+    fn total(table: &[Bitboard; 64]) -> u32 {
+        table.iter().map(|b| b.count_bits() as u32).sum()
+    }
+
+    #[test]
+    fn leaper_totals() {
+        assert!(total(&KNIGHT_ATTACKS) == 336);
+        assert!(total(&KING_ATTACKS) == 420);
+        // Pawns on the last rank attack nothing
+        assert!(total(&PAWN_ATTACKS[0]) == 98);
+        assert!(total(&PAWN_ATTACKS[1]) == 98);
+    }
+
+    #[test]
+    fn between_and_line_are_symmetric() {
+        for a in 0..64 {
+            for b in 0..64 {
+                assert!(BETWEEN[a][b] == BETWEEN[b][a]);
+                assert!(LINE[a][b] == LINE[b][a]);
+                assert!(BETWEEN[a][b].is_subset(LINE[a][b]));
+            }
+        }
+    }
+
+    #[test]
+    fn line_lengths() {
+        // a1-h1, a1-h8, e1-e2
+        assert!(LINE[0][7].count_bits() == 8);
+        assert!(LINE[0][63].count_bits() == 8);
+        assert!(LINE[4][12].v == 0x1010101010101010);
+        // a8-h1 through d5 and e4
+        assert!(LINE[35][28].count_bits() == 8);
+        // adjacent squares have nothing between them
+        assert!(BETWEEN[0][1].is_empty());
+        assert!(LINE[0][10].is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use crate::api::{GameState, Move, Side};
+use crate::bitboard::parse_fen;
+use crate::engine::{Engine, Output};
+use crate::game::Game;
+use crate::search::{Limits, Score, SearchInfo};
+
+// The Universal Chess Interface: one command per line on the input, replies on
+// the output
+pub struct Uci {
+    engine: Engine,
+    out: Output,
+    // Castling written as the king taking its rook
+    chess960: bool,
+}
+
+impl Uci {
+    pub fn new(out: Output) -> Self {
+        Uci {
+            engine: Engine::new(),
+            out,
+            chess960: false,
+        }
+    }
+
+    // Handle one command. False once told to quit.
+    pub fn handle(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("uci") => {
+                self.out.line("id name chess");
+                self.out.line("id author the chess authors");
+                self.out
+                    .line("option name UCI_Chess960 type check default false");
+                self.out.line("uciok");
+            }
+            Some("isready") => self.out.line("readyok"),
+            Some("ucinewgame") => {
+                self.engine.stop();
+                self.engine.game = Game::start();
+            }
+            Some("setoption") => self.set_option(&words.collect::<Vec<_>>()),
+            Some("position") => {
+                self.engine.stop();
+                if let Err(e) = self.position(&words.collect::<Vec<_>>()) {
+                    self.out.line(&format!("info string {}", e));
+                }
+            }
+            Some("go") => self.go(&words.collect::<Vec<_>>()),
+            Some("stop") => self.engine.stop(),
+            Some("ponderhit") => self.engine.ponderhit(),
+            Some("quit") => {
+                self.engine.stop();
+                return false;
+            }
+            Some("debug") | Some("register") | None => (),
+            Some(cmd) => self
+                .out
+                .line(&format!("info string unknown command {}", cmd)),
+        }
+        true
+    }
+
+    // setoption name <id> [value <x>]
+    fn set_option(&mut self, words: &[&str]) {
+        let value_at = words.iter().position(|&w| w == "value");
+        let name = words[..value_at.unwrap_or(words.len())]
+            .iter()
+            .skip_while(|&&w| w == "name")
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = value_at
+            .map(|i| words[i + 1..].join(" "))
+            .unwrap_or_default();
+        match name.as_str() {
+            "UCI_Chess960" => self.chess960 = value == "true",
+            _ => self
+                .out
+                .line(&format!("info string unknown option {}", name)),
+        }
+    }
+
+    // position startpos|fen <fen> [moves <m>...]. On an error the game goes
+    // up to the last legal move, or back to the start position for a bad FEN,
+    // so the previous game never stays loaded.
+    fn position(&mut self, words: &[&str]) -> Result<(), String> {
+        let moves_at = words
+            .iter()
+            .position(|&w| w == "moves")
+            .unwrap_or(words.len());
+        self.engine.game = Game::start();
+        let game = &mut self.engine.game;
+        match words.first() {
+            Some(&"startpos") => (),
+            Some(&"fen") => {
+                let fen = words[1..moves_at].join(" ");
+                *game = Game::new(parse_fen(fen).map_err(|e| e.to_string())?);
+            }
+            _ => return Err("expected startpos or fen".to_string()),
+        }
+        for s in words.iter().skip(moves_at + 1) {
+            let m = game
+                .board()
+                .parse_uci(s, self.chess960)
+                .map_err(|e| e.to_string())?;
+            if !game.play(m) {
+                return Err(format!("illegal move {}", s));
+            }
+        }
+        Ok(())
+    }
+
+    fn go(&mut self, words: &[&str]) {
+        let limits = parse_go(words);
+        let chess960 = self.chess960;
+        let (info_out, done_out) = (self.out.clone(), self.out.clone());
+        self.engine.go(
+            limits,
+            move |info| info_out.line(&info_line(info, chess960)),
+            move |m| {
+                let m = m.map_or("0000".to_string(), |m| m.to_uci(chess960));
+                done_out.line(&format!("bestmove {}", m));
+            },
+        );
+    }
+}
+
+const GO_PARAMETERS: [&str; 12] = [
+    "searchmoves",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "infinite",
+];
+
+// The limits of a `go` command. Times are in milliseconds. Anything not
+// understood is skipped, so the GUI always gets a search and a move.
+fn parse_go(words: &[&str]) -> Limits {
+    let mut limits = Limits::default();
+    let mut words = words.iter().peekable();
+    while let Some(&word) = words.next() {
+        let mut number = || -> Option<u64> {
+            let value = words.next_if(|w| !GO_PARAMETERS.contains(w))?;
+            value.parse::<i64>().ok().map(|v| v.max(0) as u64)
+        };
+        match word {
+            "depth" => limits.depth = number().map(|d| d as u32),
+            "nodes" => limits.nodes = number(),
+            "movetime" => limits.movetime = number().map(Duration::from_millis),
+            "wtime" => limits.time[Side::White as usize] = number().map(Duration::from_millis),
+            "btime" => limits.time[Side::Black as usize] = number().map(Duration::from_millis),
+            "winc" => {
+                limits.inc[Side::White as usize] = Duration::from_millis(number().unwrap_or(0))
+            }
+            "binc" => {
+                limits.inc[Side::Black as usize] = Duration::from_millis(number().unwrap_or(0))
+            }
+            "movestogo" => limits.movestogo = number().map(|n| n as u32),
+            // Mate in n takes at most 2n - 1 plies to find
+            "mate" => {
+                if let Some(n) = number() {
+                    limits.depth = Some((2 * n as u32).saturating_sub(1).max(1));
+                }
+            }
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            // The root moves aren't restricted, so the list is passed over
+            "searchmoves" => while words.next_if(|w| !GO_PARAMETERS.contains(w)).is_some() {},
+            _ => (),
+        }
+    }
+    limits
+}
+
+fn info_line(info: &SearchInfo, chess960: bool) -> String {
+    let score = match info.score {
+        Score::Cp(cp) => format!("cp {}", cp),
+        Score::Mate(n) => format!("mate {}", n),
+    };
+    let ms = info.time.as_millis() as u64;
+    let pv: Vec<String> = info.pv.iter().map(|m: &Move| m.to_uci(chess960)).collect();
+    format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        info.nodes * 1000 / ms.max(1),
+        ms,
+        pv.join(" ")
+    )
+}
+
+// Handle commands until `quit` or the end of the input
+pub fn run(lines: impl Iterator<Item = String>, out: Output) {
+    let mut uci = Uci::new(out);
+    for line in lines {
+        if !uci.handle(&line) {
+            break;
+        }
+    }
+    uci.engine.stop();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::*;
+    use crate::uci::*;
+
+    fn uci() -> (Uci, Buffer) {
+        let buffer = Buffer::default();
+        (Uci::new(Output::new(buffer.clone())), buffer)
+    }
+
+    #[test]
+    fn handshake() {
+        let (mut uci, out) = uci();
+        assert!(uci.handle("uci") && uci.handle("isready"));
+        let lines = out.lines();
+        assert!(lines[0] == "id name chess" && lines[1].starts_with("id author "));
+        assert!(lines[lines.len() - 2] == "uciok" && lines[lines.len() - 1] == "readyok");
+        assert!(!uci.handle("quit"));
+    }
+
+    #[test]
+    fn positions() {
+        let (mut uci, out) = uci();
+        uci.handle("position startpos moves e2e4 e7e5 g1f3");
+        assert!(
+            uci.engine.game.board().to_fen()
+                == "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+        assert!(uci.engine.game.ply() == 3);
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        uci.handle(&format!("position fen {} moves e1g1", fen));
+        assert!(uci.engine.game.board().to_fen() == "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1");
+        uci.handle("setoption name UCI_Chess960 value true");
+        uci.handle(&format!("position fen {} moves e1h1", fen));
+        assert!(uci.engine.game.board().to_fen() == "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1");
+        assert!(out.lines().is_empty());
+
+        uci.handle("position startpos moves e2e4 e7e5 e2e5 g1f3");
+        assert!(out.lines()[0].starts_with("info string"));
+        assert!(uci.engine.game.ply() == 2);
+        uci.handle("position fen 8/8/8 w - - 0 1");
+        assert!(out.lines().len() == 2 && uci.engine.game == Game::start());
+    }
+
+    fn go(s: &str) -> Limits {
+        parse_go(&s.split(' ').collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn go_parameters() {
+        let limits = go("wtime 1000 btime 2000 winc 10 binc 20 movestogo 5 depth 3 nodes 9");
+        assert!(limits.time == [Some(Duration::from_secs(1)), Some(Duration::from_secs(2))]);
+        assert!(limits.inc == [Duration::from_millis(10), Duration::from_millis(20)]);
+        assert!(limits.movestogo == Some(5) && limits.depth == Some(3) && limits.nodes == Some(9));
+        assert!(go("infinite").infinite);
+        let limits = go("ponder wtime 1000 btime 2000");
+        assert!(limits.ponder && !limits.infinite && limits.time[0].is_some());
+
+        // Unknown and malformed parameters are passed over
+        assert!(go("searchmoves e2e4 d2d4 depth 3").depth == Some(3));
+        assert!(go("mate 2").depth == Some(3));
+        assert!(go("movetime") == Limits::default());
+        assert!(go("depth x nodes 5") == go("nodes 5"));
+        assert!(go("wibble 4 depth 2").depth == Some(2));
+    }
+
+    #[test]
+    fn search_streams_info_then_bestmove() {
+        let (mut uci, out) = uci();
+        uci.handle("position fen 4k3/8/8/7q/8/8/8/4K2R b - - 0 1");
+        uci.handle("go movetime 100");
+        uci.engine.wait();
+        let lines = out.lines();
+        assert!(lines[0].starts_with("info depth 1 score cp "));
+        assert!(lines[0].ends_with(" pv h5h1"));
+        assert!(lines.last().unwrap() == "bestmove h5h1");
+
+        uci.handle("go infinite");
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(
+            out.lines()
+                .iter()
+                .filter(|l| l.starts_with("bestmove"))
+                .count()
+                == 1
+        );
+        uci.handle("stop");
+        assert!(
+            out.lines()
+                .iter()
+                .filter(|l| l.starts_with("bestmove"))
+                .count()
+                == 2
+        );
+    }
+}
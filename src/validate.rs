@@ -0,0 +1,223 @@
+use crate::api::{BoardSide, File, Piece, Rank, Side, Square};
+use crate::attacks::is_attacked_by;
+use crate::bitboard::{BbBoardState, BbPieceState, Bitboard, PIECES};
+
+// Reasons a position can't arise in a game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PositionProblem {
+    // More than one piece on the square
+    Overlap(Square),
+    // A side with no king, or more than one
+    KingCount(Side, u8),
+    PawnOnBackRank(Square),
+    // The side that just moved left its king in check
+    OpponentInCheck,
+    // A castling right with the rook on the given file, but the king isn't on the
+    // back rank or the rook isn't on that side of it
+    BadCastling(Side, File),
+    // The en passant square doesn't follow a double push
+    BadEnPassant(Square),
+    // More pieces than eight pawns and their promotions allow
+    TooManyPieces(Side),
+}
+
+impl BbPieceState {
+    // Problems that only depend on piece placement
+    pub fn validate(&self) -> Vec<PositionProblem> {
+        let mut problems = Vec::new();
+
+        let mut seen = Bitboard::empty();
+        let mut overlap = Bitboard::empty();
+        for side in [Side::White, Side::Black] {
+            for piece in PIECES {
+                let bb = self.get(side, piece);
+                overlap |= seen & bb;
+                seen |= bb;
+            }
+        }
+        problems.extend(overlap.into_iter().map(PositionProblem::Overlap));
+
+        for side in [Side::White, Side::Black] {
+            let kings = self.get(side, Piece::K).count_bits();
+            if kings != 1 {
+                problems.push(PositionProblem::KingCount(side, kings));
+            }
+        }
+
+        let back_ranks = Bitboard::rank(Rank::R1) | Bitboard::rank(Rank::R8);
+        let pawns = self.get(Side::White, Piece::P) | self.get(Side::Black, Piece::P);
+        problems.extend(
+            (pawns & back_ranks)
+                .into_iter()
+                .map(PositionProblem::PawnOnBackRank),
+        );
+
+        for side in [Side::White, Side::Black] {
+            if self.promoted_at_least(side) > 8 - self.get(side, Piece::P).count_bits().min(8) {
+                problems.push(PositionProblem::TooManyPieces(side));
+            }
+        }
+
+        problems
+    }
+
+    // Pieces beyond the starting set, each of which needs a pawn to have promoted
+    fn promoted_at_least(&self, side: Side) -> u8 {
+        let extra =
+            |piece: Piece, start: u8| self.get(side, piece).count_bits().saturating_sub(start);
+        let pawns = self.get(side, Piece::P).count_bits();
+        extra(Piece::Q, 1)
+            + extra(Piece::R, 2)
+            + extra(Piece::B, 2)
+            + extra(Piece::N, 2)
+            + pawns.saturating_sub(8)
+    }
+}
+
+impl BbBoardState {
+    // Everything wrong with the position. Empty when it could arise in a game,
+    // as far as can be told without the moves that led to it.
+    pub fn validate(&self) -> Vec<PositionProblem> {
+        let mut problems = self.pieces.validate();
+
+        let them = self.to_move.opposite();
+        let their_king = self.pieces.get(them, Piece::K);
+        if their_king.count_bits() == 1
+            && is_attacked_by(
+                &self.pieces,
+                their_king.lsb(),
+                self.to_move,
+                self.pieces.occupied(),
+            )
+        {
+            problems.push(PositionProblem::OpponentInCheck);
+        }
+
+        for (side, wing, file) in self.castling.iter() {
+            if !self.castling_pieces_in_place(side, wing, file) {
+                problems.push(PositionProblem::BadCastling(side, file));
+            }
+        }
+
+        if let Some(ep) = self.en_passant {
+            if !self.en_passant_is_consistent(ep) {
+                problems.push(PositionProblem::BadEnPassant(ep));
+            }
+        }
+
+        problems
+    }
+
+    // The king on its back rank with the rook on the right side of it
+    fn castling_pieces_in_place(&self, side: Side, wing: BoardSide, rook_file: File) -> bool {
+        let rook = Square::from_rank_file(side.back_rank(), rook_file);
+        self.pieces.castling_king_file(side).is_some()
+            && self.pieces.castling_wing(side, rook_file) == wing
+            && self.pieces.piece_at(rook) == Some((side, Piece::R))
+    }
+
+    // The square must be empty, on the rank behind a pawn of the side that just
+    // moved, with the square the pawn came from empty as well
+    fn en_passant_is_consistent(&self, ep: Square) -> bool {
+        let (rank, pawn, origin) = match self.to_move {
+            Side::White => (Rank::R6, ep.v.wrapping_sub(8), ep.v + 8),
+            Side::Black => (Rank::R3, ep.v + 8, ep.v.wrapping_sub(8)),
+        };
+        ep.rank_index() == rank as u8
+            && self.pieces.piece_at(ep).is_none()
+            && self.pieces.piece_at(Square::new(origin)).is_none()
+            && self.pieces.piece_at(Square::new(pawn)) == Some((self.to_move.opposite(), Piece::P))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::bitboard::*;
+    use crate::validate::*;
+
+    fn problems(fen: &str) -> Vec<PositionProblem> {
+        parse_fen(fen.to_string()).unwrap().validate()
+    }
+
+    #[test]
+    fn valid_positions_have_no_problems() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+            "4k3/8/8/8/8/8/8/QQQQKQQQ w - - 0 1",
+        ];
+        for fen in fens {
+            assert!(problems(fen).is_empty(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn overlapping_pieces() {
+        let mut board = parse_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        board.pieces.wn = Bitboard::get_coord(Square::new(4));
+        board.pieces.bq = Bitboard::get_coord(Square::new(4));
+        let p = board.validate();
+        assert!(p == vec![PositionProblem::Overlap(Square::new(4))]);
+        assert!(!board.is_legal());
+    }
+
+    #[test]
+    fn king_counts() {
+        let p = problems("4k3/8/8/8/8/8/8/8 w - - 0 1");
+        assert!(p == vec![PositionProblem::KingCount(Side::White, 0)]);
+        let p = problems("4k3/8/8/8/8/8/8/K3K3 w - - 0 1");
+        assert!(p == vec![PositionProblem::KingCount(Side::White, 2)]);
+    }
+
+    #[test]
+    fn pawns_on_back_ranks() {
+        let p = problems("P3k3/8/8/8/8/8/8/4K2p w - - 0 1");
+        assert!(p.contains(&PositionProblem::PawnOnBackRank(Square::new(56))));
+        assert!(p.contains(&PositionProblem::PawnOnBackRank(Square::new(7))));
+        assert!(p.len() == 2);
+    }
+
+    #[test]
+    fn side_not_to_move_in_check() {
+        let p = problems("4k3/8/8/8/8/8/8/4K2R w - - 0 1");
+        assert!(p.is_empty());
+        let p = problems("4k2R/8/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(p == vec![PositionProblem::OpponentInCheck]);
+    }
+
+    #[test]
+    fn castling_rights_need_king_and_rook() {
+        let p = problems("r3k3/8/8/8/8/8/8/R3K3 w Kq - 0 1");
+        assert!(p == vec![PositionProblem::BadCastling(Side::White, File::H)]);
+        let p = problems("r7/3k4/8/8/8/8/8/4K2R w Kq - 0 1");
+        assert!(p == vec![PositionProblem::BadCastling(Side::Black, File::A)]);
+    }
+
+    #[test]
+    fn en_passant_must_follow_a_double_push() {
+        let mut board = parse_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1".to_string()).unwrap();
+        assert!(board.validate().is_empty());
+        // No pawn in front of the square
+        board.en_passant = Some(Square::new(42));
+        assert!(board.validate() == vec![PositionProblem::BadEnPassant(Square::new(42))]);
+        // Wrong rank for the side to move
+        board.en_passant = Some(Square::new(19));
+        assert!(board.validate() == vec![PositionProblem::BadEnPassant(Square::new(19))]);
+    }
+
+    #[test]
+    fn impossible_piece_counts() {
+        // Nine pawns
+        let p = problems("4k3/8/8/8/8/P7/PPPPPPPP/4K3 w - - 0 1");
+        assert!(p == vec![PositionProblem::TooManyPieces(Side::White)]);
+        // Two extra queens but only seven pawns could have promoted
+        let p = problems("4k3/pppppppp/8/8/8/8/PPPPPPP1/2QQKQ2 b - - 0 1");
+        assert!(p == vec![PositionProblem::TooManyPieces(Side::White)]);
+        let p = problems("4k3/pppppppp/8/8/8/8/PPPPPP2/2QQKQ2 b - - 0 1");
+        assert!(p.is_empty());
+        let p = problems("nnnnk3/ppppppp1/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(p == vec![PositionProblem::TooManyPieces(Side::Black)]);
+    }
+}
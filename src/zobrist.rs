@@ -0,0 +1,194 @@
+use std::sync::OnceLock;
+
+use crate::api::{Piece, Side, Square};
+use crate::bitboard::{BbBoardState, PIECES};
+use crate::rnd::{RndGen, Xoshiro256p};
+
+// Fixed seed so hashes are stable between runs and can be stored
+pub const ZOBRIST_SEED: u64 = 0x007a_6f62_7269_7374;
+
+pub struct ZobristKeys {
+    // Indexed by `Side as usize`, `Piece as usize` and square
+    pieces: [[[u64; 64]; 6]; 2],
+    pub side: u64,
+    // Indexed by `Side as usize`, then `BoardSide as usize`. Rights never move to
+    // another rook, so the rook file doesn't need a key of its own.
+    castling: [[u64; 2]; 2],
+    en_passant: [u64; 8],
+}
+
+static KEYS: OnceLock<ZobristKeys> = OnceLock::new();
+
+pub fn keys() -> &'static ZobristKeys {
+    KEYS.get_or_init(|| {
+        let mut rnd = Xoshiro256p::initialize(ZOBRIST_SEED);
+        let mut pieces = [[[0; 64]; 6]; 2];
+        for side in pieces.iter_mut() {
+            for piece in side.iter_mut() {
+                for key in piece.iter_mut() {
+                    *key = rnd.next();
+                }
+            }
+        }
+        let side = rnd.next();
+        let castling = [[rnd.next(), rnd.next()], [rnd.next(), rnd.next()]];
+        let mut en_passant = [0; 8];
+        for key in en_passant.iter_mut() {
+            *key = rnd.next();
+        }
+        ZobristKeys {
+            pieces,
+            side,
+            castling,
+            en_passant,
+        }
+    })
+}
+
+impl ZobristKeys {
+    #[inline]
+    pub fn piece(&self, side: Side, piece: Piece, s: Square) -> u64 {
+        self.pieces[side as usize][piece as usize][s.v as usize]
+    }
+
+    // Combined key of the castling rights currently held
+    pub fn castling(&self, board: &BbBoardState) -> u64 {
+        board.castling.iter().fold(0, |h, (side, wing, _)| {
+            h ^ self.castling[side as usize][wing as usize]
+        })
+    }
+
+    // Keyed by the file of the en passant square
+    pub fn en_passant(&self, s: Option<Square>) -> u64 {
+        s.map_or(0, |s| self.en_passant[s.file_index() as usize])
+    }
+}
+
+impl BbBoardState {
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    // Hash of the position built from scratch, which the incrementally updated
+    // `hash` must always match
+    pub fn compute_hash(&self) -> u64 {
+        let keys = keys();
+        let mut h = 0;
+        for side in [Side::White, Side::Black] {
+            for piece in PIECES {
+                for s in self.pieces.get(side, piece) {
+                    h ^= keys.piece(side, piece, s);
+                }
+            }
+        }
+        if self.to_move == Side::Black {
+            h ^= keys.side;
+        }
+        h ^ keys.castling(self) ^ self.en_passant_hash()
+    }
+
+    // Key of the en passant square, left out when no capture is possible. The
+    // square is set after every double push, and would otherwise tell apart
+    // positions that are the same for repetitions.
+    pub(crate) fn en_passant_hash(&self) -> u64 {
+        match self.has_legal_en_passant() {
+            true => keys().en_passant(self.en_passant),
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Board, BoardSide, File, Rank, Side, Square};
+    use crate::bitboard::*;
+    use crate::rnd::*;
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    fn check_tree(board: &mut BbBoardState, depth: u8) {
+        assert!(board.hash() == board.compute_hash());
+        if depth == 0 {
+            return;
+        }
+        for m in board.legal_moves() {
+            let before = board.hash();
+            let undo = board.make_move(m);
+            check_tree(board, depth - 1);
+            board.unmake_move(m, undo);
+            assert!(board.hash() == before);
+        }
+    }
+
+    #[test]
+    fn incremental_hash_matches_full_hash() {
+        check_tree(&mut parse_fen(KIWIPETE.to_string()).unwrap(), 3);
+        let promotions = "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1";
+        check_tree(&mut parse_fen(promotions.to_string()).unwrap(), 3);
+    }
+
+    #[test]
+    fn random_games_keep_hash_in_sync() {
+        let mut rnd = Xoshiro256p::initialize(1);
+        for _ in 0..20 {
+            let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+            let mut board = parse_fen(fen.to_string()).unwrap();
+            for _ in 0..100 {
+                let moves = board.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                let m = moves[(rnd.next() % moves.len() as u64) as usize];
+                board.make_move(m);
+                assert!(board.hash() == board.compute_hash());
+            }
+        }
+    }
+
+    #[test]
+    fn transpositions_hash_equal() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let start = parse_fen(fen.to_string()).unwrap();
+        let play = |moves: &[(u8, u8)]| {
+            let mut board = start.clone();
+            for &(from, to) in moves {
+                let m = *board
+                    .legal_moves()
+                    .iter()
+                    .find(|m| m.from().v == from && m.to().v == to)
+                    .unwrap();
+                board.make_move(m);
+            }
+            board.hash()
+        };
+        // 1. Nf3 Nf6 2. Nc3 against 1. Nc3 Nf6 2. Nf3
+        let a = play(&[(6, 21), (62, 45), (1, 18)]);
+        let b = play(&[(1, 18), (62, 45), (6, 21)]);
+        assert!(a == b);
+        // Knights out and back returns to the start position
+        let c = play(&[(6, 21), (62, 45), (21, 6), (45, 62)]);
+        assert!(c == start.hash());
+    }
+
+    #[test]
+    fn side_castling_and_en_passant_change_the_hash() {
+        let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        let mut board = parse_fen(fen.to_string()).unwrap();
+        let h = board.compute_hash();
+        board.en_passant = Some(Square::from_rank_file(Rank::R3, File::E));
+        assert!(board.compute_hash() != h);
+        board.en_passant = None;
+        board.to_move = Side::White;
+        assert!(board.compute_hash() != h);
+        board.to_move = Side::Black;
+        board.castling.set(Side::Black, BoardSide::QueenSide, None);
+        assert!(board.compute_hash() != h);
+
+        // A square no pawn can take leaves the hash alone
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let board = parse_fen(fen.to_string()).unwrap();
+        assert!(board.compute_hash() == board.hash());
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        assert!(parse_fen(fen.to_string()).unwrap().hash() == board.hash());
+    }
+}